@@ -0,0 +1,255 @@
+use std::{
+    fs, io,
+    os::unix,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+use log::trace;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::dir::{Dir, HOME_DIR, ensure_exists, get};
+
+pub static BACKUP_INDEX_FILE: &str = "index.toml";
+pub static BACKUP_TIMESTAMP_FORMAT: &str = "%Y%m%d%H%M%S";
+/// Extension of the backups earlier versions left next to the destination
+pub static LEGACY_BACKUP_EXTENSION: &str = "bak";
+
+#[derive(Debug, Error)]
+pub enum BackupError {
+    #[error("Failed to read backup index {0}: {1}")]
+    CannotRead(PathBuf, io::Error),
+    #[error("Malformed backup index {0}: {1}")]
+    Malformed(PathBuf, toml::de::Error),
+    #[error("Failed to serialize backup index: {0}")]
+    CannotSerialize(toml::ser::Error),
+    #[error("Failed to write backup index {0}: {1}")]
+    CannotWrite(PathBuf, io::Error),
+    #[error("Cannot back up {0}, {1} is already taken in the backup store")]
+    AlreadyStored(PathBuf, PathBuf),
+    #[error("Failed to move {0} into the backup store: {1}")]
+    CannotMove(PathBuf, io::Error),
+}
+
+/// Everything `link --force` moved out of the way, kept under `$XDG_STATE_HOME/dottie/backups/<timestamp>/`
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct BackupIndex {
+    /// The id the next backup gets, ids are never reused
+    #[serde(default)]
+    next_id: u64,
+    #[serde(default, rename = "backup")]
+    records: Vec<BackupRecord>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupRecord {
+    pub id: u64,
+    /// The package whose link took the place of the backup
+    pub pkg_name: String,
+    /// Where the backup was, and is restored to
+    pub dest_path: PathBuf,
+    /// Where the backup is within the store
+    pub backup_path: PathBuf,
+    /// When the backup was made
+    pub created_at: DateTime<Utc>,
+}
+
+impl BackupIndex {
+    pub fn path() -> PathBuf {
+        get(Dir::Backups).join(BACKUP_INDEX_FILE)
+    }
+
+    /// Load the index, an absent index is an empty one
+    pub fn load() -> eyre::Result<Self> {
+        let path = Self::path();
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                trace!("No backup index at {:?}, starting empty", path);
+                return Ok(Self::default());
+            }
+            Err(e) => return Err(BackupError::CannotRead(path, e).into()),
+        };
+        Ok(toml::from_str(&content).map_err(|e| BackupError::Malformed(path, e))?)
+    }
+
+    pub fn save(&self) -> eyre::Result<()> {
+        ensure_exists(Dir::Backups)?;
+        let path = Self::path();
+        let content = toml::to_string(self).map_err(BackupError::CannotSerialize)?;
+        fs::write(&path, content).map_err(|e| BackupError::CannotWrite(path, e))?;
+        Ok(())
+    }
+
+    pub fn records(&self) -> &[BackupRecord] {
+        &self.records
+    }
+
+    pub fn find(&self, id: u64) -> Option<&BackupRecord> {
+        self.records.iter().find(|record| record.id == id)
+    }
+
+    /// The newest backup of the destination, if any
+    pub fn latest_for(&self, dest_path: &Path) -> Option<&BackupRecord> {
+        self.records
+            .iter()
+            .filter(|record| record.dest_path == dest_path)
+            .max_by_key(|record| (record.created_at, record.id))
+    }
+
+    /// Move what is at the destination into the store and record it, giving where it went.
+    ///
+    /// It goes to `<timestamp>/<path>`, the path taken relative to the home directory, or to
+    /// the root for destinations outside of it.
+    pub fn store(&mut self, pkg_name: &str, dest_path: &Path) -> eyre::Result<PathBuf> {
+        let created_at = Utc::now();
+        let relative = dest_path
+            .strip_prefix(HOME_DIR.as_path())
+            .or_else(|_| dest_path.strip_prefix("/"))
+            .unwrap_or(dest_path);
+        let backup_path = get(Dir::Backups)
+            .join(created_at.format(BACKUP_TIMESTAMP_FORMAT).to_string())
+            .join(relative);
+        if backup_path.symlink_metadata().is_ok() {
+            Err(BackupError::AlreadyStored(
+                dest_path.to_owned(),
+                backup_path.clone(),
+            ))?;
+        }
+
+        if let Some(parent) = backup_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        move_path(dest_path, &backup_path)
+            .map_err(|e| BackupError::CannotMove(dest_path.to_owned(), e))?;
+        trace!("Backed up {:?} to {:?}", dest_path, backup_path);
+
+        self.next_id += 1;
+        self.records.push(BackupRecord {
+            id: self.next_id,
+            pkg_name: pkg_name.to_owned(),
+            dest_path: dest_path.to_owned(),
+            backup_path: backup_path.clone(),
+            created_at,
+        });
+        Ok(backup_path)
+    }
+
+    /// Move the backup back to the destination and forget it, the destination must be free
+    pub fn restore(&mut self, backup_path: &Path, dest_path: &Path) -> io::Result<()> {
+        if let Some(parent) = dest_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        move_path(backup_path, dest_path)?;
+        trace!("Restored {:?} to {:?}", backup_path, dest_path);
+        self.forget(backup_path);
+        Ok(())
+    }
+
+    /// Forget the backup once it has been moved out of the store
+    pub fn forget(&mut self, backup_path: &Path) {
+        self.records
+            .retain(|record| record.backup_path != backup_path);
+        remove_empty_parents(backup_path);
+    }
+
+    /// Delete the backup for good and forget it, a backup already gone is just forgotten
+    pub fn discard(&mut self, id: u64) -> io::Result<()> {
+        let Some(record) = self.find(id) else {
+            return Ok(());
+        };
+        let backup_path = record.backup_path.clone();
+        match remove_path(&backup_path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => (),
+        }
+        trace!("Discarded backup {:?}", backup_path);
+        self.records.retain(|record| record.id != id);
+        remove_empty_parents(&backup_path);
+        Ok(())
+    }
+}
+
+/// Find the newest `<name>-<timestamp>.bak` that `link --force` left next to the destination
+/// before backups went into the store
+pub fn legacy_backup_of(dest_path: &Path) -> io::Result<Option<PathBuf>> {
+    let (Some(parent), Some(file_name)) = (dest_path.parent(), dest_path.file_name()) else {
+        return Ok(None);
+    };
+    let prefix = format!("{}-", file_name.to_string_lossy());
+    let postfix = format!(".{}", LEGACY_BACKUP_EXTENSION);
+
+    let mut latest: Option<(String, PathBuf)> = None;
+    for entry in parent.read_dir()? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let Some(timestamp) = name
+            .strip_prefix(&prefix)
+            .and_then(|rest| rest.strip_suffix(&postfix))
+        else {
+            continue;
+        };
+        if timestamp.is_empty() || !timestamp.chars().all(|c| c.is_ascii_digit()) {
+            continue;
+        }
+        if latest
+            .as_ref()
+            .is_none_or(|(newest, _)| timestamp > newest.as_str())
+        {
+            latest = Some((timestamp.to_owned(), entry.path()));
+        }
+    }
+
+    Ok(latest.map(|(_, path)| path))
+}
+
+/// Move a file, directory or symlink, copying it over when it lives on another filesystem
+pub fn move_path(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            copy_path(from, to)?;
+            remove_path(from)
+        }
+        result => result,
+    }
+}
+
+/// Copy a file, directory or symlink as it is, keeping permissions
+fn copy_path(from: &Path, to: &Path) -> io::Result<()> {
+    let meta = from.symlink_metadata()?;
+    if meta.is_symlink() {
+        unix::fs::symlink(from.read_link()?, to)
+    } else if meta.is_dir() {
+        fs::create_dir(to)?;
+        for entry in from.read_dir()? {
+            let entry = entry?;
+            copy_path(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        fs::set_permissions(to, meta.permissions())
+    } else {
+        fs::copy(from, to).map(|_| ())
+    }
+}
+
+fn remove_path(path: &Path) -> io::Result<()> {
+    if path.symlink_metadata()?.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+/// Remove the directories a backup leaves empty within the store
+fn remove_empty_parents(backup_path: &Path) {
+    let store = get(Dir::Backups);
+    for parent in backup_path
+        .ancestors()
+        .skip(1)
+        .take_while(|parent| parent.starts_with(&store) && *parent != store)
+    {
+        if fs::remove_dir(parent).is_err() {
+            break;
+        }
+    }
+}
@@ -0,0 +1,378 @@
+use std::{
+    fs, io,
+    os::unix,
+    path::{self, Path, PathBuf},
+};
+
+use clap::Args;
+use log::{trace, warn};
+use owo_colors::OwoColorize;
+use thiserror::Error;
+
+use crate::{
+    backup::move_path,
+    dir::{APP_DIR, Dir, exists, get},
+    ledger::{LinkLedger, LinkRecord},
+    manifest::Manifest,
+};
+
+#[derive(Debug, Args)]
+pub struct AdoptArg {
+    /// The package the files will be moved into
+    #[arg(
+        value_name = "package",
+        help = "The package the files will be moved into"
+    )]
+    pub pkg_name: String,
+
+    /// The existing files or directories to adopt
+    #[arg(
+        value_name = "paths",
+        required = true,
+        help = "The existing files or directories to adopt, within the package target"
+    )]
+    pub paths: Vec<PathBuf>,
+
+    /// Dry run mode, only prints what would be moved and linked
+    #[arg(
+        short,
+        long,
+        help = "Dry run mode, only print what would be moved and linked"
+    )]
+    pub dry: bool,
+}
+
+// LYN: Main
+
+pub fn main(arg: &AdoptArg) -> eyre::Result<()> {
+    if !exists(Dir::Pkg {
+        pkg_name: arg.pkg_name.to_owned(),
+    })? {
+        Err(AdoptError::PkgNotFound(arg.pkg_name.to_owned()))?;
+    }
+    let manifest = Manifest::load(&arg.pkg_name)?;
+    let target = manifest.target_dir()?;
+
+    let files = get(Dir::Files {
+        pkg_name: arg.pkg_name.to_owned(),
+    });
+    let adoptions = plan_all(&arg.paths, &target, &files, &APP_DIR.canonicalize()?)?;
+
+    let mut ledger = LinkLedger::load()?;
+    println!("{}", "Adopt Summary:".bold().bright_green());
+    for adoption in &adoptions {
+        if !arg.dry {
+            adopt(adoption)?;
+            ledger.record(LinkRecord::symlink(
+                &arg.pkg_name,
+                &adoption.src_path,
+                &adoption.dest_path,
+                None,
+            ));
+            ledger.save()?;
+        }
+        println!(
+            "- {} {} {}",
+            if arg.dry { "Planed" } else { "Adopted" }.bright_green(),
+            format!("`{}`", adoption.dest_path.display()).cyan(),
+            format!("-> `{}`", adoption.src_path.display()).bright_black(),
+        );
+    }
+
+    Ok(())
+}
+
+// LYN: Adopt Files
+
+#[derive(Debug, Error)]
+enum AdoptError {
+    #[error("Package `{0}` doesn't exist, create it with `dottie new`")]
+    PkgNotFound(String),
+    #[error("Cannot adopt {0}: {1}")]
+    NotFound(PathBuf, io::Error),
+    #[error("Cannot adopt {0}, it is not within the package target {1}")]
+    OutsideTarget(PathBuf, PathBuf),
+    #[error("Cannot adopt {0}, it is already managed by dottie")]
+    AlreadyManaged(PathBuf),
+    #[error("Cannot adopt {0}, it is a symlink")]
+    Symlink(PathBuf),
+    #[error("Cannot adopt {0}, {1} already exists in the package")]
+    AlreadyInPackage(PathBuf, PathBuf),
+    #[error("Cannot adopt {0} together with {1}, one is within the other")]
+    Overlapping(PathBuf, PathBuf),
+    #[error("Failed to move {0} into the package: {1}")]
+    CannotMove(PathBuf, io::Error),
+    #[error("Failed to link {0}, it was moved back: {1}")]
+    CannotLink(PathBuf, io::Error),
+}
+
+/// A file or directory to move into the package and link back
+#[derive(Debug)]
+struct Adoption {
+    /// Where it ends up inside the package
+    src_path: PathBuf,
+    /// Where it is now, and where the link will be
+    dest_path: PathBuf,
+}
+
+/// Plan every path before touching any, so a bad one leaves all in place
+fn plan_all(
+    paths: &[PathBuf],
+    target: &Path,
+    files: &Path,
+    app_dir: &Path,
+) -> eyre::Result<Vec<Adoption>> {
+    let mut adoptions: Vec<Adoption> = Vec::new();
+    for path in paths {
+        let adoption = plan(path, target, files, app_dir)?;
+        if let Some(other) = adoptions.iter().find(|other| {
+            other.src_path.starts_with(&adoption.src_path)
+                || adoption.src_path.starts_with(&other.src_path)
+        }) {
+            Err(AdoptError::Overlapping(
+                adoption.dest_path.clone(),
+                other.dest_path.clone(),
+            ))?;
+        }
+        adoptions.push(adoption);
+    }
+    Ok(adoptions)
+}
+
+/// Work out where the path goes in the package files, refusing anything dottie shouldn't take.
+///
+/// The app directory is given as its real location.
+fn plan(path: &Path, target: &Path, files: &Path, app_dir: &Path) -> eyre::Result<Adoption> {
+    // Without a trailing slash, which a link cannot be made at
+    let dest_path: PathBuf = path::absolute(path)?.components().collect();
+    let meta = dest_path
+        .symlink_metadata()
+        .map_err(|e| AdoptError::NotFound(dest_path.clone(), e))?;
+    if meta.is_symlink() {
+        if dest_path
+            .canonicalize()
+            .is_ok_and(|real| real.starts_with(app_dir))
+        {
+            Err(AdoptError::AlreadyManaged(dest_path.clone()))?;
+        }
+        Err(AdoptError::Symlink(dest_path.clone()))?;
+    }
+
+    // Compare real locations, the path may be reached through symlinked directories
+    let real = dest_path.canonicalize()?;
+    if real.starts_with(app_dir) {
+        Err(AdoptError::AlreadyManaged(dest_path.clone()))?;
+    }
+    let target = target.canonicalize()?;
+    let relative = match real.strip_prefix(&target) {
+        Ok(relative) if relative.components().next().is_some() => relative,
+        _ => Err(AdoptError::OutsideTarget(dest_path.clone(), target.clone()))?,
+    };
+
+    let src_path = files.join(relative);
+    if src_path.symlink_metadata().is_ok() {
+        Err(AdoptError::AlreadyInPackage(
+            dest_path.clone(),
+            src_path.clone(),
+        ))?;
+    }
+    trace!("Planned adopting {:?} as {:?}", dest_path, src_path);
+    Ok(Adoption {
+        src_path,
+        dest_path,
+    })
+}
+
+/// Move the file into the package and link it back, moving it back if linking fails
+fn adopt(adoption: &Adoption) -> eyre::Result<()> {
+    let Adoption {
+        src_path,
+        dest_path,
+    } = adoption;
+    if let Some(parent) = src_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    move_path(dest_path, src_path).map_err(|e| AdoptError::CannotMove(dest_path.clone(), e))?;
+    trace!("Moved {:?} to {:?}", dest_path, src_path);
+    if let Err(e) = unix::fs::symlink(src_path, dest_path) {
+        warn!("Failed to link {:?}, moving it back", dest_path);
+        move_path(src_path, dest_path)?;
+        Err(AdoptError::CannotLink(dest_path.clone(), e))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Scratch directories standing in for the app directory, package files and target
+    struct Scratch {
+        root: PathBuf,
+        app_dir: PathBuf,
+        files: PathBuf,
+        target: PathBuf,
+    }
+
+    impl Scratch {
+        fn new(name: &str) -> Self {
+            let root = std::env::temp_dir().join(format!("dottie-{}-{name}", std::process::id()));
+            let _ = fs::remove_dir_all(&root);
+            fs::create_dir_all(&root).unwrap();
+            let root = root.canonicalize().unwrap();
+            let app_dir = root.join("app");
+            let files = app_dir.join("pkg").join("files");
+            let target = root.join("home");
+            fs::create_dir_all(&files).unwrap();
+            fs::create_dir_all(target.join(".config")).unwrap();
+            Self {
+                root,
+                app_dir,
+                files,
+                target,
+            }
+        }
+
+        fn plan(&self, paths: &[PathBuf]) -> eyre::Result<Vec<Adoption>> {
+            plan_all(paths, &self.target, &self.files, &self.app_dir)
+        }
+
+        /// The refusal of planning the paths
+        fn refusal(&self, paths: &[PathBuf]) -> AdoptError {
+            let report = self.plan(paths).unwrap_err();
+            match report.downcast::<AdoptError>() {
+                Ok(e) => e,
+                Err(report) => panic!("not a refusal: {report}"),
+            }
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    #[test]
+    fn plan_places_path_relative_to_target() {
+        let scratch = Scratch::new("adopt-plan");
+        let path = scratch.target.join(".config/app.toml");
+        fs::write(&path, "").unwrap();
+
+        let adoptions = scratch.plan(std::slice::from_ref(&path)).unwrap();
+        assert_eq!(adoptions[0].dest_path, path);
+        assert_eq!(
+            adoptions[0].src_path,
+            scratch.files.join(".config/app.toml")
+        );
+    }
+
+    #[test]
+    fn plan_refuses_outside_target() {
+        let scratch = Scratch::new("adopt-outside");
+        let outside = scratch.root.join("outside");
+        fs::write(&outside, "").unwrap();
+
+        assert!(matches!(
+            scratch.refusal(&[outside]),
+            AdoptError::OutsideTarget(..)
+        ));
+        assert!(matches!(
+            scratch.refusal(std::slice::from_ref(&scratch.target)),
+            AdoptError::OutsideTarget(..)
+        ));
+        assert!(matches!(
+            scratch.refusal(&[scratch.target.join("missing")]),
+            AdoptError::NotFound(..)
+        ));
+    }
+
+    #[test]
+    fn plan_refuses_dottie_symlink_and_other_symlinks() {
+        let scratch = Scratch::new("adopt-symlink");
+        let managed = scratch.files.join("rc");
+        fs::write(&managed, "").unwrap();
+        unix::fs::symlink(&managed, scratch.target.join("rc")).unwrap();
+        let elsewhere = scratch.root.join("elsewhere");
+        fs::write(&elsewhere, "").unwrap();
+        unix::fs::symlink(&elsewhere, scratch.target.join("other")).unwrap();
+
+        assert!(matches!(
+            scratch.refusal(&[scratch.target.join("rc")]),
+            AdoptError::AlreadyManaged(..)
+        ));
+        assert!(matches!(
+            scratch.refusal(&[scratch.target.join("other")]),
+            AdoptError::Symlink(..)
+        ));
+        assert!(matches!(
+            scratch.refusal(&[managed]),
+            AdoptError::AlreadyManaged(..)
+        ));
+    }
+
+    #[test]
+    fn plan_refuses_overlapping_paths() {
+        let scratch = Scratch::new("adopt-overlap");
+        let file = scratch.target.join(".config/app.toml");
+        fs::write(&file, "").unwrap();
+
+        assert!(matches!(
+            scratch.refusal(&[scratch.target.join(".config"), file]),
+            AdoptError::Overlapping(..)
+        ));
+    }
+
+    #[test]
+    fn plan_refuses_path_already_in_package() {
+        let scratch = Scratch::new("adopt-in-package");
+        fs::write(scratch.target.join(".config/app.toml"), "").unwrap();
+        fs::create_dir_all(scratch.files.join(".config")).unwrap();
+        fs::write(scratch.files.join(".config/app.toml"), "").unwrap();
+
+        assert!(matches!(
+            scratch.refusal(&[scratch.target.join(".config/app.toml")]),
+            AdoptError::AlreadyInPackage(..)
+        ));
+    }
+
+    #[test]
+    fn adopt_moves_and_links_back() {
+        let scratch = Scratch::new("adopt-move");
+        let path = scratch.target.join(".config");
+        fs::write(path.join("app.toml"), "content").unwrap();
+
+        // Given with a trailing slash, as shells complete directories
+        let adoptions = scratch
+            .plan(&[PathBuf::from(format!("{}/", path.display()))])
+            .unwrap();
+        adopt(&adoptions[0]).unwrap();
+        assert_eq!(path.read_link().unwrap(), scratch.files.join(".config"));
+        assert_eq!(
+            fs::read_to_string(path.join("app.toml")).unwrap(),
+            "content"
+        );
+    }
+
+    #[test]
+    fn adopt_moves_back_when_link_fails() {
+        let scratch = Scratch::new("adopt-rollback");
+        let dir = scratch.target.join("dir");
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join("file"), "content").unwrap();
+
+        // A link cannot be made at a path ending in a slash, though the directory moves
+        let adoption = Adoption {
+            src_path: scratch.files.join("dir"),
+            dest_path: scratch.target.join("dir/"),
+        };
+        let report = adopt(&adoption).unwrap_err();
+        assert!(matches!(
+            report.downcast_ref::<AdoptError>(),
+            Some(AdoptError::CannotLink(..))
+        ));
+        assert!(!dir.is_symlink());
+        assert_eq!(fs::read_to_string(dir.join("file")).unwrap(), "content");
+        assert!(!scratch.files.join("dir").exists());
+    }
+}
@@ -0,0 +1,208 @@
+use std::{
+    path::{self, PathBuf},
+    time::Duration,
+};
+
+use chrono::{Local, Utc};
+use clap::{Args, Subcommand};
+use owo_colors::OwoColorize;
+use thiserror::Error;
+
+use crate::{
+    backup::{BackupIndex, BackupRecord},
+    dir::{Dir, expand_path, get},
+    duration,
+    ledger::LinkLedger,
+};
+
+#[derive(Debug, Args)]
+pub struct BackupArg {
+    #[command(subcommand)]
+    pub command: BackupCommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum BackupCommand {
+    #[clap(about = "List the backups in the store, oldest first")]
+    List,
+
+    #[clap(about = "Move a backup back to where it was")]
+    Restore(RestoreArg),
+
+    #[clap(about = "Delete backups older than a given age")]
+    Prune(PruneArg),
+}
+
+#[derive(Debug, Args)]
+pub struct RestoreArg {
+    /// The id of the backup, or the path it was backed up from
+    #[arg(
+        value_name = "id|path",
+        help = "The id of the backup, or the path it was backed up from (restores the newest)"
+    )]
+    pub backup: String,
+}
+
+#[derive(Debug, Args)]
+pub struct PruneArg {
+    /// Delete backups made longer ago than this
+    #[arg(
+        long,
+        value_name = "duration",
+        value_parser = duration::parse,
+        help = "Delete backups made longer ago than this, e.g. `30d`"
+    )]
+    pub older_than: Duration,
+
+    /// Dry run mode, only prints what backups would be deleted
+    #[arg(
+        short,
+        long,
+        help = "Dry run mode, only print what backups would be deleted"
+    )]
+    pub dry: bool,
+}
+
+// LYN: Main
+
+pub fn main(arg: &BackupArg) -> eyre::Result<()> {
+    let mut backups = BackupIndex::load()?;
+    match &arg.command {
+        BackupCommand::List => display(backups.records()),
+        BackupCommand::Restore(arg) => restore(arg, &mut backups)?,
+        BackupCommand::Prune(arg) => prune(arg, &mut backups)?,
+    }
+    Ok(())
+}
+
+// LYN: Backup Store
+
+#[derive(Debug, Error)]
+enum BackupCliError {
+    #[error("No backup with id or path `{0}`, see `dottie backup list`")]
+    NotFound(String),
+    #[error("Cannot restore backup #{0}, {1} is occupied, unlink or move it away first")]
+    DestOccupied(u64, PathBuf),
+    #[error("Backup #{0} is missing from the store at {1}")]
+    Missing(u64, PathBuf),
+    #[error("Duration `{0}` is too long")]
+    TooLong(String),
+}
+
+fn display(records: &[BackupRecord]) {
+    println!("{}", "Backups:".bold().bright_green());
+    if records.is_empty() {
+        println!("- No backups in `{}`", get(Dir::Backups).display());
+    }
+    for record in records {
+        println!(
+            "- {} {} {}{}",
+            format!("#{}", record.id).bright_blue(),
+            format!("`{}`", record.dest_path.display()).cyan(),
+            format!(
+                "from {}, replaced by package `{}`",
+                record
+                    .created_at
+                    .with_timezone(&Local)
+                    .format("%Y-%m-%d %H:%M:%S"),
+                record.pkg_name,
+            )
+            .bright_black(),
+            if record.backup_path.symlink_metadata().is_ok() {
+                String::new()
+            } else {
+                " (missing)".bright_red().to_string()
+            },
+        );
+    }
+}
+
+/// Find the backup by id, or the newest one of the path, given as destination or in the store
+fn find<'a>(backups: &'a BackupIndex, raw: &str) -> eyre::Result<&'a BackupRecord> {
+    if let Ok(id) = raw.trim_start_matches('#').parse()
+        && let Some(record) = backups.find(id)
+    {
+        return Ok(record);
+    }
+    let path = if raw.starts_with('~') {
+        expand_path(raw)?
+    } else {
+        path::absolute(raw)?
+    };
+    let record = backups.latest_for(&path).or_else(|| {
+        backups
+            .records()
+            .iter()
+            .find(|record| record.backup_path == path)
+    });
+    Ok(record.ok_or_else(|| BackupCliError::NotFound(raw.to_owned()))?)
+}
+
+fn restore(arg: &RestoreArg, backups: &mut BackupIndex) -> eyre::Result<()> {
+    let BackupRecord {
+        id,
+        dest_path,
+        backup_path,
+        ..
+    } = find(backups, &arg.backup)?.clone();
+    if backup_path.symlink_metadata().is_err() {
+        Err(BackupCliError::Missing(id, backup_path.clone()))?;
+    }
+    if dest_path.symlink_metadata().is_ok() {
+        Err(BackupCliError::DestOccupied(id, dest_path.clone()))?;
+    }
+
+    backups.restore(&backup_path, &dest_path)?;
+    backups.save()?;
+    let mut ledger = LinkLedger::load()?;
+    ledger.forget_backup(&backup_path);
+    ledger.save()?;
+
+    println!(
+        "{} {} {}",
+        "Restored".bold().bright_green(),
+        format!("`{}`", dest_path.display()).cyan(),
+        format!("<- `{}`", backup_path.display()).bright_black(),
+    );
+    Ok(())
+}
+
+fn prune(arg: &PruneArg, backups: &mut BackupIndex) -> eyre::Result<()> {
+    let too_long = || BackupCliError::TooLong(duration::display(arg.older_than));
+    let cutoff = chrono::Duration::from_std(arg.older_than)
+        .ok()
+        .and_then(|age| Utc::now().checked_sub_signed(age))
+        .ok_or_else(too_long)?;
+    let pruned: Vec<_> = backups
+        .records()
+        .iter()
+        .filter(|record| record.created_at < cutoff)
+        .cloned()
+        .collect();
+
+    let mut ledger = LinkLedger::load()?;
+    println!("{}", "Prune Summary:".bold().bright_green());
+    if pruned.is_empty() {
+        println!(
+            "- No backups older than {}",
+            duration::display(arg.older_than)
+        );
+    }
+    for record in &pruned {
+        if !arg.dry {
+            backups.discard(record.id)?;
+            ledger.forget_backup(&record.backup_path);
+        }
+        println!(
+            "- {} {} {}",
+            if arg.dry { "Planed" } else { "Deleted" }.bright_green(),
+            format!("#{}", record.id).bright_blue(),
+            format!("`{}`", record.dest_path.display()).cyan(),
+        );
+    }
+    if !arg.dry {
+        backups.save()?;
+        ledger.save()?;
+    }
+    Ok(())
+}
@@ -0,0 +1,69 @@
+use std::{io, os::unix::process::ExitStatusExt, process::ExitStatus, time::Duration};
+
+use clap::ValueEnum;
+use serde::{Serialize, Serializer, ser::SerializeStruct};
+
+/// How a summary is printed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// Coloured text for humans
+    #[default]
+    Text,
+    /// A single JSON document once everything is done
+    Json,
+    /// One JSON record per line, each package as soon as it is done, then the summary
+    Ndjson,
+}
+
+impl Format {
+    /// Whether the output is meant for programs, so nothing else may be printed to stdout
+    pub fn is_machine(self) -> bool {
+        self != Format::Text
+    }
+}
+
+/// A line of `ndjson` output
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Record<'a, P, S> {
+    Package(&'a P),
+    Summary(&'a S),
+}
+
+/// Print the value as a JSON document, or as a single line in `ndjson` format
+pub fn print_json<T: Serialize>(value: &T, format: Format) -> eyre::Result<()> {
+    let json = match format {
+        Format::Ndjson => serde_json::to_string(value)?,
+        _ => serde_json::to_string_pretty(value)?,
+    };
+    println!("{json}");
+    Ok(())
+}
+
+/// Serialize an exit status with its code or the signal that ended it
+pub fn exit_status<S: Serializer>(status: &ExitStatus, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut state = serializer.serialize_struct("ExitStatus", 3)?;
+    state.serialize_field("success", &status.success())?;
+    state.serialize_field("code", &status.code())?;
+    state.serialize_field("signal", &status.signal())?;
+    state.end()
+}
+
+/// Serialize a duration as whole seconds
+pub fn seconds<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(duration.as_secs())
+}
+
+/// Serialize the outcome of an action, `null` if it was not attempted
+pub fn outcome<S: Serializer>(
+    outcome: &Option<io::Result<()>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let Some(result) = outcome else {
+        return serializer.serialize_none();
+    };
+    let mut state = serializer.serialize_struct("Outcome", 2)?;
+    state.serialize_field("ok", &result.is_ok())?;
+    state.serialize_field("error", &result.as_ref().err().map(io::Error::to_string))?;
+    state.end()
+}
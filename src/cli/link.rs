@@ -1,17 +1,32 @@
 use std::{
-    ffi::OsString,
-    fs, io,
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt::{self, Display, Formatter},
+    fs, io, mem,
     os::unix,
     path::{Path, PathBuf},
+    process::ExitCode,
 };
 
-use chrono::Utc;
 use clap::Args;
-use log::{error, info, trace, warn};
+use log::{trace, warn};
 use owo_colors::OwoColorize;
+use serde::Serialize;
 use thiserror::Error;
 
-use crate::dir::{Dir, HOME_DIR, exists, get, not_package};
+use crate::{
+    backup::BackupIndex,
+    cli::format::{self, Format, Record},
+    dir::{APP_DIR, Dir, get},
+    exit::{self, Failure},
+    host::Host,
+    ledger::{LinkLedger, LinkMode, LinkRecord, bytes_hash, content_hash},
+    manifest::Manifest,
+    pattern::Patterns,
+    pkg::{self, DepIssue, Selection},
+    template::{TemplateError, Vars, has_template_within, is_template, render},
+    variant::{self, Pick, base_name},
+};
 
 #[derive(Debug, Args)]
 pub struct LinkArg {
@@ -19,7 +34,6 @@ pub struct LinkArg {
     #[arg(
         value_name = "packages",
         group = "specify",
-        required = true,
         help = "The package(s) whose files will be linked"
     )]
     pub pkgs: Vec<String>,
@@ -28,6 +42,15 @@ pub struct LinkArg {
     #[arg(short, long, group = "specify", help = "Link files for all packages")]
     pub all: bool,
 
+    /// Link files for the packages of a profile, defaults to the profile of this machine
+    #[arg(
+        short,
+        long,
+        group = "specify",
+        help = "Link files for the packages of a profile, defaults to the profile of this machine"
+    )]
+    pub profile: Option<String>,
+
     /// Force link and override if possible, even if some files may not be linked
     #[arg(
         short,
@@ -39,43 +62,94 @@ pub struct LinkArg {
     /// Dry run mode, only prints what files would be linked
     #[arg(short, long, help = "Dry run mode, only print what files would linked")]
     pub dry: bool,
+
+    /// Do not pull in the dependencies of the named packages
+    #[arg(long, help = "Do not pull in the dependencies of the named packages")]
+    pub no_deps: bool,
+
+    /// How the summary is printed
+    #[arg(long, value_enum, default_value_t, help = "How the summary is printed")]
+    pub format: Format,
 }
 
 // LYN: Main
 
-pub fn main(arg: &LinkArg) -> eyre::Result<()> {
-    let summary = if arg.all {
-        link_all(arg)?
-    } else {
-        link_specified(arg)?
-    };
+pub fn main(arg: &LinkArg) -> eyre::Result<ExitCode> {
+    let mut ledger = LinkLedger::load()?;
+    let vars = Vars::load()?;
+    let selection = pkg::select(&arg.pkgs, arg.all, arg.profile.as_deref(), !arg.no_deps)?;
+    let linked = link_selected(arg, selection, &vars, &mut ledger);
+    // Saved even when linking stopped halfway, so the ledger knows every link made
+    if !arg.dry {
+        ledger.save()?;
+    }
+    let mut summary = linked?;
+    let code = exit::code_of(summary.failures());
 
-    summary.display();
+    match arg.format {
+        Format::Text => summary.display(),
+        Format::Json => format::print_json(&summary, arg.format)?,
+        Format::Ndjson => {
+            // Packages were printed as their files were linked
+            summary.details.clear();
+            format::print_json(&Record::<LinkDetail, _>::Summary(&summary), arg.format)?;
+        }
+    }
 
-    Ok(())
+    Ok(code)
 }
 
 // LYN: Link Summary
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Serialize)]
 struct LinkSummary {
+    /// The profile the packages were selected by
+    profile: Option<String>,
     /// Packages whose files were linked and the linking detail
+    #[serde(skip_serializing_if = "Vec::is_empty")]
     details: Vec<LinkDetail>,
     /// package names that do not exist
     non_exist: Vec<String>,
     /// package names that do not have files
     no_files: Vec<String>,
+    /// Packages skipped because of their dependencies
+    dep_issues: Vec<DepIssue>,
 }
 
 impl LinkSummary {
+    /// What went wrong, deciding the exit code
+    fn failures(&self) -> Vec<Failure> {
+        let mut failures = Vec::new();
+        if !self.non_exist.is_empty() || !self.dep_issues.is_empty() {
+            failures.push(Failure::PackageMissing);
+        }
+        if self
+            .details
+            .iter()
+            .flat_map(|detail| &detail.detail_pack)
+            .any(LinkDetailPack::failed)
+        {
+            failures.push(Failure::LinkFailed);
+        }
+        failures
+    }
+
     fn display(&self) {
         println!("{}", "Link Summary:".bold().bright_green());
+        if let Some(profile) = &self.profile {
+            println!("- Using profile {}", format!("`{}`", profile).yellow());
+        }
 
         for detail in &self.details {
             println!(
-                "- Package {} processed {} file(s)",
+                "- Package {} processed {} file(s) {}",
                 format!("`{}`", detail.pkg_name).yellow(),
-                detail.detail_pack.len()
+                detail
+                    .detail_pack
+                    .iter()
+                    .filter(|pack| pack.is_destination())
+                    .count(),
+                format!("in `{}`", detail.target.display()).bright_black(),
             );
             for pack in &detail.detail_pack {
                 match pack {
@@ -122,8 +196,15 @@ impl LinkSummary {
                     LinkDetailPack::DestOccupied {
                         src_path,
                         dest_path,
+                        mode,
+                        occupant,
                         force_linked,
+                        backup_path,
                     } => {
+                        let arrow = match mode {
+                            LinkMode::Symlink => "->",
+                            _ => "<-",
+                        };
                         if let Some(force_linked) = force_linked {
                             if let Err(e) = force_linked {
                                 println!(
@@ -131,22 +212,251 @@ impl LinkSummary {
                                     "Failed".bold().on_red(),
                                     format!("`{}`", dest_path.display()).cyan(),
                                 );
+                            } else {
+                                println!(
+                                    "  - {} {} {}",
+                                    if occupant.is_replaceable() {
+                                        "Replaced"
+                                    } else {
+                                        "Forced"
+                                    }
+                                    .bright_green(),
+                                    format!("`{}`", dest_path.display()).cyan(),
+                                    format!(
+                                        "{arrow} `{}`{}, replacing {occupant}{}",
+                                        src_path.display(),
+                                        mode_note(*mode),
+                                        backup_note(backup_path)
+                                    )
+                                    .bright_black(),
+                                );
+                            }
+                        } else if occupant.is_replaceable() {
+                            println!(
+                                "  - {} {} {}",
+                                "Planed".bright_green(),
+                                format!("`{}`", dest_path.display()).cyan(),
+                                format!(
+                                    "{arrow} `{}`{}, replacing {occupant}",
+                                    src_path.display(),
+                                    mode_note(*mode)
+                                )
+                                .bright_black(),
+                            );
+                        } else {
+                            println!(
+                                "  - {} {} is {} {}",
+                                "Failed".bold().on_red(),
+                                format!("`{}`", dest_path.display()).cyan(),
+                                occupant,
+                                match occupant {
+                                    Occupant::LinkedToOtherPackage { .. } => {
+                                        "(`--force` takes it over)"
+                                    }
+                                    _ => "(`--force` backs it up and replaces it)",
+                                }
+                                .bright_black(),
+                            );
+                        }
+                    }
+                    LinkDetailPack::Copyable {
+                        src_path,
+                        dest_path,
+                        mode,
+                        copied,
+                    } => {
+                        if let Some(copied) = copied {
+                            if let Err(e) = copied {
+                                println!(
+                                    "  - {} {} due to {e}",
+                                    "Failed".bold().on_red(),
+                                    format!("`{}`", dest_path.display()).cyan(),
+                                );
+                            } else {
+                                println!(
+                                    "  - {} {} {}",
+                                    match mode {
+                                        LinkMode::Template => "Rendered",
+                                        _ => "Copied",
+                                    }
+                                    .bright_green(),
+                                    format!("`{}`", dest_path.display()).cyan(),
+                                    format!("<- `{}`{}", src_path.display(), mode_note(*mode))
+                                        .bright_black(),
+                                );
+                            }
+                        } else {
+                            println!(
+                                "  - {} {} {}",
+                                "Planed".bright_green(),
+                                format!("`{}`", dest_path.display()).cyan(),
+                                format!("<- `{}`{}", src_path.display(), mode_note(*mode))
+                                    .bright_black(),
+                            );
+                        }
+                    }
+                    LinkDetailPack::AlreadyCopied {
+                        src_path,
+                        dest_path,
+                        mode,
+                    } => {
+                        println!(
+                            "  - {} {} {}",
+                            "UpToDate".bright_green(),
+                            format!("`{}`", dest_path.display()).cyan(),
+                            format!("<- `{}`{}", src_path.display(), mode_note(*mode))
+                                .bright_black(),
+                        );
+                    }
+                    LinkDetailPack::CopySourceChanged {
+                        src_path,
+                        dest_path,
+                        mode,
+                        copied,
+                    } => {
+                        let changed = match mode {
+                            LinkMode::Template => "output differs from disk",
+                            _ => "source changed",
+                        };
+                        if let Some(copied) = copied {
+                            if let Err(e) = copied {
+                                println!(
+                                    "  - {} {} due to {e}",
+                                    "Failed".bold().on_red(),
+                                    format!("`{}`", dest_path.display()).cyan(),
+                                );
+                            } else {
+                                println!(
+                                    "  - {} {} {}",
+                                    "Updated".bright_green(),
+                                    format!("`{}`", dest_path.display()).cyan(),
+                                    format!(
+                                        "<- `{}`{}, {changed}",
+                                        src_path.display(),
+                                        mode_note(*mode)
+                                    )
+                                    .bright_black(),
+                                );
+                            }
+                        } else {
+                            println!(
+                                "  - {} {} {}",
+                                "Planed".bright_green(),
+                                format!("`{}`", dest_path.display()).cyan(),
+                                format!(
+                                    "<- `{}`{}, {changed}",
+                                    src_path.display(),
+                                    mode_note(*mode)
+                                )
+                                .bright_black(),
+                            );
+                        }
+                    }
+                    LinkDetailPack::CopyDestEdited {
+                        src_path,
+                        dest_path,
+                        mode,
+                        force_copied,
+                        backup_path,
+                    } => {
+                        if let Some(force_copied) = force_copied {
+                            if let Err(e) = force_copied {
+                                println!(
+                                    "  - {} {} due to {e}",
+                                    "Failed".bold().on_red(),
+                                    format!("`{}`", dest_path.display()).cyan(),
+                                );
                             } else {
                                 println!(
                                     "  - {} {} {}",
                                     "Forced".bright_green(),
                                     format!("`{}`", dest_path.display()).cyan(),
-                                    format!("-> `{}`", src_path.display()).bright_black(),
+                                    format!(
+                                        "<- `{}`{}{}",
+                                        src_path.display(),
+                                        mode_note(*mode),
+                                        backup_note(backup_path)
+                                    )
+                                    .bright_black(),
                                 );
                             }
                         } else {
                             println!(
-                                "  - {} {} exists and is not a valid symlink",
+                                "  - {} {} was edited locally since last {}",
                                 "Failed".bold().on_red(),
                                 format!("`{}`", dest_path.display()).cyan(),
+                                match mode {
+                                    LinkMode::Template => "rendered",
+                                    _ => "copied",
+                                }
                             );
                         }
                     }
+                    LinkDetailPack::Ignored { src_path } => {
+                        println!(
+                            "  - {} {} {}",
+                            "Ignored".bright_black(),
+                            format!("`{}`", src_path.display()).cyan(),
+                            "matched an ignore pattern".bright_black(),
+                        );
+                    }
+                    LinkDetailPack::VariantChosen {
+                        src_path,
+                        dest_path,
+                        reason,
+                        others,
+                    } => {
+                        println!(
+                            "  - {} {} {}",
+                            "Variant".bright_blue(),
+                            format!("`{}`", dest_path.display()).cyan(),
+                            format!(
+                                "<- `{}`, {reason}{}",
+                                src_path.display(),
+                                if *others > 0 {
+                                    format!(", over {others} other variant(s)")
+                                } else {
+                                    String::new()
+                                }
+                            )
+                            .bright_black(),
+                        );
+                    }
+                    LinkDetailPack::NoVariant {
+                        dest_path,
+                        candidates,
+                    } => {
+                        println!(
+                            "  - {} {} {}",
+                            "Skipped".bright_black(),
+                            format!("`{}`", dest_path.display()).cyan(),
+                            format!("none of {candidates} variant(s) matches this machine")
+                                .bright_black(),
+                        );
+                    }
+                    LinkDetailPack::Overridden { dest_path, by, .. } => {
+                        println!(
+                            "  - {} {} {}",
+                            "Skipped".bright_black(),
+                            format!("`{}`", dest_path.display()).cyan(),
+                            format!("overridden by package `{by}`").bright_black(),
+                        );
+                    }
+                    LinkDetailPack::Collision {
+                        dest_path, with, ..
+                    } => {
+                        println!(
+                            "  - {} {} is also claimed by {} {}",
+                            "Failed".bold().on_red(),
+                            format!("`{}`", dest_path.display()).cyan(),
+                            with.iter()
+                                .map(|pkg_name| format!("package `{pkg_name}`"))
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                                .yellow(),
+                            "(set `priority` or `overrides` in a manifest)".bright_black(),
+                        );
+                    }
                 }
             }
         }
@@ -162,137 +472,653 @@ impl LinkSummary {
                 format!("`{}`", pkg_name).yellow()
             );
         }
+        for issue in &self.dep_issues {
+            println!("- {}", issue);
+        }
+    }
+}
+
+/// Note telling templated files apart in the summary
+fn mode_note(mode: LinkMode) -> &'static str {
+    match mode {
+        LinkMode::Template => " (templated)",
+        _ => "",
+    }
+}
+
+/// Note telling where a forced destination was backed up to
+fn backup_note(backup_path: &Option<PathBuf>) -> String {
+    match backup_path {
+        Some(backup_path) => format!(", backed up to `{}`", backup_path.display()),
+        None => String::new(),
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 struct LinkDetail {
     /// The name of the package whose files were link
     pkg_name: String,
+    /// The directory the package files are linked into
+    target: PathBuf,
     /// The detail of the files linked for the package
     detail_pack: Vec<LinkDetailPack>,
 }
 
-#[derive(Debug)]
-enum LinkDetailPack {
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub(crate) enum LinkDetailPack {
     Linkable {
         src_path: PathBuf,
         dest_path: PathBuf,
+        #[serde(serialize_with = "format::outcome")]
         linked: Option<io::Result<()>>,
     },
     AlreadyLinked {
         src_path: PathBuf,
         dest_path: PathBuf,
     },
+    /// Something else is at the destination, replaced only with `--force` unless harmless
     DestOccupied {
         src_path: PathBuf,
         dest_path: PathBuf,
+        mode: LinkMode,
+        /// What is at the destination
+        occupant: Occupant,
+        #[serde(serialize_with = "format::outcome")]
         force_linked: Option<io::Result<()>>,
+        /// Where the occupied destination was moved when forced
+        backup_path: Option<PathBuf>,
+    },
+    /// Copied or rendered, depending on `mode`
+    Copyable {
+        src_path: PathBuf,
+        dest_path: PathBuf,
+        mode: LinkMode,
+        #[serde(serialize_with = "format::outcome")]
+        copied: Option<io::Result<()>>,
     },
+    AlreadyCopied {
+        src_path: PathBuf,
+        dest_path: PathBuf,
+        mode: LinkMode,
+    },
+    CopySourceChanged {
+        src_path: PathBuf,
+        dest_path: PathBuf,
+        mode: LinkMode,
+        #[serde(serialize_with = "format::outcome")]
+        copied: Option<io::Result<()>>,
+    },
+    CopyDestEdited {
+        src_path: PathBuf,
+        dest_path: PathBuf,
+        mode: LinkMode,
+        #[serde(serialize_with = "format::outcome")]
+        force_copied: Option<io::Result<()>>,
+        /// Where the edited destination was moved when forced
+        backup_path: Option<PathBuf>,
+    },
+    Ignored {
+        src_path: PathBuf,
+    },
+    /// Precedes the detail of the variant picked for the destination
+    VariantChosen {
+        src_path: PathBuf,
+        dest_path: PathBuf,
+        reason: String,
+        others: usize,
+    },
+    NoVariant {
+        dest_path: PathBuf,
+        candidates: usize,
+    },
+    /// The destination is claimed by another selected package, which wins it
+    Overridden {
+        src_path: PathBuf,
+        dest_path: PathBuf,
+        by: String,
+    },
+    /// The destination is claimed by other selected packages, none of which wins it
+    Collision {
+        src_path: PathBuf,
+        dest_path: PathBuf,
+        with: Vec<String>,
+    },
+}
+
+/// What occupies a destination that is not what linking would make
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub(crate) enum Occupant {
+    /// A symlink into the files of a package, another one or an older layout of this one
+    LinkedToOtherPackage {
+        pkg_name: String,
+        target: PathBuf,
+        /// The package lost the destination to this one, so it is taken over without `--force`
+        takeover: bool,
+    },
+    /// A symlink to something outside dottie
+    ForeignSymlink { target: PathBuf },
+    /// A symlink to nothing, replaced without `--force` as nothing is lost
+    DanglingSymlink { target: PathBuf },
+    /// A regular file
+    OccupiedByFile,
+    /// A directory where a file or a symlink belongs
+    OccupiedByDir,
+}
+
+impl Occupant {
+    /// Tell what occupies the destination, which must exist or be a symlink
+    pub(crate) fn of(dest_path: &Path) -> Self {
+        let Ok(target) = dest_path.read_link() else {
+            return if dest_path.is_dir() {
+                Occupant::OccupiedByDir
+            } else {
+                Occupant::OccupiedByFile
+            };
+        };
+        let Ok(real) = dest_path.canonicalize() else {
+            return Occupant::DanglingSymlink { target };
+        };
+        let pkg_name = APP_DIR.canonicalize().ok().and_then(|app_dir| {
+            real.strip_prefix(app_dir)
+                .ok()?
+                .components()
+                .next()
+                .map(|pkg_name| pkg_name.as_os_str().to_string_lossy().into_owned())
+        });
+        match pkg_name {
+            Some(pkg_name) => Occupant::LinkedToOtherPackage {
+                pkg_name,
+                target,
+                takeover: false,
+            },
+            None => Occupant::ForeignSymlink { target },
+        }
+    }
+
+    /// Whether it may be replaced without `--force`
+    pub(crate) fn is_replaceable(&self) -> bool {
+        matches!(
+            self,
+            Occupant::DanglingSymlink { .. }
+                | Occupant::LinkedToOtherPackage { takeover: true, .. }
+        )
+    }
+}
+
+impl Display for Occupant {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Occupant::LinkedToOtherPackage { pkg_name, .. } => {
+                write!(f, "a symlink into package `{pkg_name}`")
+            }
+            Occupant::ForeignSymlink { target } => {
+                write!(f, "a symlink to `{}`", target.display())
+            }
+            Occupant::DanglingSymlink { target } => {
+                write!(f, "a dangling symlink to `{}`", target.display())
+            }
+            Occupant::OccupiedByFile => write!(f, "a file"),
+            Occupant::OccupiedByDir => write!(f, "a directory"),
+        }
+    }
+}
+
+impl LinkDetailPack {
+    /// Whether the pack is about a destination the package makes, rather than a note
+    pub(crate) fn is_destination(&self) -> bool {
+        self.claim().is_some()
+    }
+
+    /// The source and destination of the pack, if it is about a destination the package makes
+    fn claim(&self) -> Option<(&Path, &Path)> {
+        match self {
+            LinkDetailPack::Linkable {
+                src_path,
+                dest_path,
+                ..
+            }
+            | LinkDetailPack::AlreadyLinked {
+                src_path,
+                dest_path,
+            }
+            | LinkDetailPack::DestOccupied {
+                src_path,
+                dest_path,
+                ..
+            }
+            | LinkDetailPack::Copyable {
+                src_path,
+                dest_path,
+                ..
+            }
+            | LinkDetailPack::AlreadyCopied {
+                src_path,
+                dest_path,
+                ..
+            }
+            | LinkDetailPack::CopySourceChanged {
+                src_path,
+                dest_path,
+                ..
+            }
+            | LinkDetailPack::CopyDestEdited {
+                src_path,
+                dest_path,
+                ..
+            }
+            | LinkDetailPack::Collision {
+                src_path,
+                dest_path,
+                ..
+            } => Some((src_path, dest_path)),
+            LinkDetailPack::Ignored { .. }
+            | LinkDetailPack::VariantChosen { .. }
+            | LinkDetailPack::NoVariant { .. }
+            | LinkDetailPack::Overridden { .. } => None,
+        }
+    }
+
+    /// Whether the file failed to link, occupied destinations fail unless forced
+    fn failed(&self) -> bool {
+        match self {
+            LinkDetailPack::Linkable { linked: result, .. }
+            | LinkDetailPack::Copyable { copied: result, .. }
+            | LinkDetailPack::CopySourceChanged { copied: result, .. } => {
+                matches!(result, Some(Err(_)))
+            }
+            LinkDetailPack::DestOccupied {
+                occupant,
+                force_linked: None,
+                ..
+            } => !occupant.is_replaceable(),
+            LinkDetailPack::DestOccupied {
+                force_linked: result,
+                ..
+            }
+            | LinkDetailPack::CopyDestEdited {
+                force_copied: result,
+                ..
+            } => !matches!(result, Some(Ok(()))),
+            LinkDetailPack::Collision { .. } => true,
+            _ => false,
+        }
+    }
 }
 
 // LYN: Linke Files
 
 #[derive(Debug, Error)]
 enum LinkError {
-    #[error("Failed to parse UTF8 OsString: {0:?}")]
-    InvalidUtf8OsString(OsString),
     #[error("Impossible nameless path generated by program: {0}")]
     ImpossibleNamelessPath(PathBuf),
     #[error("Cannot confirm existence of {0}: {1}")]
     CannotConfirmFileExistence(PathBuf, io::Error),
 }
 
-/// Link files for all packages
-fn link_all(arg: &LinkArg) -> eyre::Result<LinkSummary> {
-    let mut summary = LinkSummary::default();
-    for pkg_entry in get(Dir::App).read_dir()? {
-        let pkg_entry = pkg_entry?;
-        if not_package(&pkg_entry.path()) {
-            info!("Skipping non backage entry: {:?}", pkg_entry);
-            continue;
-        }
-        let pkg_name = pkg_entry
-            .file_name()
-            .into_string()
-            .map_err(LinkError::InvalidUtf8OsString)?;
-        if !exists(Dir::Files {
-            pkg_name: pkg_name.to_owned(),
-        })? {
-            summary.no_files.push(pkg_name.to_owned());
-            warn!("Package `{}` does not have a files folder", pkg_name);
-            continue;
+/// Link files for the selected packages, dependencies first
+fn link_selected(
+    arg: &LinkArg,
+    mut selection: Selection,
+    vars: &Vars,
+    ledger: &mut LinkLedger,
+) -> eyre::Result<LinkSummary> {
+    let mut summary = LinkSummary {
+        profile: selection.profile.clone(),
+        non_exist: mem::take(&mut selection.non_exist),
+        dep_issues: mem::take(&mut selection.dep_issues),
+        ..Default::default()
+    };
+
+    // Probe every package before linking any, so destinations they share are settled first
+    let mut plans = Vec::new();
+    for pkg_name in &selection.ordered {
+        let manifest = selection.manifest(pkg_name)?;
+        match PkgPlan::prob(pkg_name, &manifest, vars, ledger)? {
+            Some(plan) => plans.push(plan),
+            None => {
+                summary.no_files.push(pkg_name.to_owned());
+                warn!("Package `{}` does not have a files folder", pkg_name);
+            }
         }
+    }
+    settle_collisions(&mut plans);
 
-        let mut detail_pack = prob_link(
-            &get(Dir::Files {
-                pkg_name: pkg_name.clone(),
-            }),
-            HOME_DIR.as_path(),
-        )?;
+    for mut plan in plans {
         if !arg.dry {
-            make_link(&mut detail_pack, arg)?;
+            make_link(
+                &plan.pkg_name,
+                &mut plan.detail_pack,
+                arg,
+                &plan.rules,
+                ledger,
+            )?;
         }
-        summary.details.push(LinkDetail {
-            pkg_name,
-            detail_pack,
-        });
+        let detail = LinkDetail {
+            pkg_name: plan.pkg_name,
+            target: plan.target,
+            detail_pack: plan.detail_pack,
+        };
+        if arg.format == Format::Ndjson {
+            format::print_json(&Record::<_, LinkSummary>::Package(&detail), arg.format)?;
+        }
+        summary.details.push(detail);
     }
-
     Ok(summary)
 }
 
-/// Link files for specified packages
-fn link_specified(arg: &LinkArg) -> eyre::Result<LinkSummary> {
-    let mut summary = LinkSummary::default();
-    for pkg_name in &arg.pkgs {
-        if !exists(Dir::Pkg {
+/// How a package would be linked, probed without linking anything
+pub(crate) struct PkgPlan {
+    pub(crate) pkg_name: String,
+    /// The directory the package files are linked into
+    target: PathBuf,
+    rules: LinkRules,
+    pub(crate) detail_pack: Vec<LinkDetailPack>,
+    /// Decides which package links a destination several packages claim, the highest wins
+    priority: i32,
+    /// Packages this one wins shared destinations from, regardless of priority
+    overrides: Vec<String>,
+}
+
+impl PkgPlan {
+    /// Test how every file of the package would be linked, `None` if it has no files
+    pub(crate) fn prob(
+        pkg_name: &str,
+        manifest: &Manifest,
+        vars: &Vars,
+        ledger: &LinkLedger,
+    ) -> eyre::Result<Option<Self>> {
+        let files = get(Dir::Files {
             pkg_name: pkg_name.to_owned(),
-        })? {
-            summary.non_exist.push(pkg_name.to_owned());
-            warn!("Package `{}` does not exist", pkg_name);
-            continue;
+        });
+        if !files.is_dir() {
+            return Ok(None);
         }
-        if !exists(Dir::Scripts {
+        let target = manifest.target_dir()?;
+        let rules = LinkRules::for_pkg(pkg_name, manifest, vars)?;
+        let detail_pack = prob_link(&files, &target, &rules, ledger)?;
+        Ok(Some(Self {
             pkg_name: pkg_name.to_owned(),
-        })? {
-            summary.no_files.push(pkg_name.to_owned());
-            warn!("Package `{}` does not have a scripts folder", pkg_name);
+            target,
+            rules,
+            detail_pack,
+            priority: manifest.priority,
+            overrides: manifest.overrides.clone(),
+        }))
+    }
+
+    /// Whether this package wins a shared destination over the other, `Equal` for a tie
+    fn precedence(&self, other: &PkgPlan) -> Ordering {
+        match (
+            self.overrides.contains(&other.pkg_name),
+            other.overrides.contains(&self.pkg_name),
+        ) {
+            (true, false) => Ordering::Greater,
+            (false, true) => Ordering::Less,
+            _ => self.priority.cmp(&other.priority),
+        }
+    }
+}
+
+/// Settle destinations claimed by several packages, including one within another.
+///
+/// A package loses the destination to any claimant it doesn't take precedence over, and a
+/// winner takes over symlinks into the packages it beat. Ties are left as collisions.
+pub(crate) fn settle_collisions(plans: &mut [PkgPlan]) {
+    let mut claims: HashMap<&Path, Vec<(usize, usize)>> = HashMap::new();
+    for (i, plan) in plans.iter().enumerate() {
+        for (j, pack) in plan.detail_pack.iter().enumerate() {
+            if let Some((_, dest_path)) = pack.claim() {
+                claims.entry(dest_path).or_default().push((i, j));
+            }
+        }
+    }
+    let mut rivals: BTreeMap<(usize, usize), BTreeSet<usize>> = BTreeMap::new();
+    for (dest_path, claimants) in &claims {
+        for ancestor in dest_path.ancestors() {
+            let Some(others) = claims.get(ancestor) else {
+                continue;
+            };
+            for &(i, j) in claimants {
+                for &(k, l) in others.iter().filter(|(k, _)| *k != i) {
+                    rivals.entry((i, j)).or_default().insert(k);
+                    rivals.entry((k, l)).or_default().insert(i);
+                }
+            }
+        }
+    }
+
+    let mut verdicts = Vec::new();
+    for ((i, j), rivals) in rivals {
+        let plan = &plans[i];
+        let against = |ordering| {
+            rivals
+                .iter()
+                .map(|&k| &plans[k])
+                .filter(move |other| plan.precedence(other) == ordering)
+                .map(|other| other.pkg_name.clone())
+        };
+        let verdict = if let Some(by) = against(Ordering::Less).next() {
+            Verdict::Lost(by)
+        } else {
+            let with: Vec<_> = against(Ordering::Equal).collect();
+            if with.is_empty() {
+                Verdict::Won(against(Ordering::Greater).collect())
+            } else {
+                Verdict::Tied(with)
+            }
+        };
+        verdicts.push((i, j, verdict));
+    }
+
+    for (i, j, verdict) in verdicts {
+        let pack = &mut plans[i].detail_pack[j];
+        let Some((src_path, dest_path)) = pack
+            .claim()
+            .map(|(src_path, dest_path)| (src_path.to_owned(), dest_path.to_owned()))
+        else {
             continue;
+        };
+        match verdict {
+            Verdict::Won(beaten) => {
+                if let LinkDetailPack::DestOccupied {
+                    occupant:
+                        Occupant::LinkedToOtherPackage {
+                            pkg_name, takeover, ..
+                        },
+                    ..
+                } = pack
+                {
+                    *takeover = beaten.contains(pkg_name);
+                }
+            }
+            Verdict::Lost(by) => {
+                trace!("Package `{}` wins {:?}", by, dest_path);
+                *pack = LinkDetailPack::Overridden {
+                    src_path,
+                    dest_path,
+                    by,
+                };
+            }
+            Verdict::Tied(with) => {
+                trace!("Packages {:?} collide at {:?}", with, dest_path);
+                *pack = LinkDetailPack::Collision {
+                    src_path,
+                    dest_path,
+                    with,
+                };
+            }
         }
+    }
+}
 
-        let mut detail_pack = prob_link(
-            &get(Dir::Files {
-                pkg_name: pkg_name.to_owned(),
-            }),
-            HOME_DIR.as_path(),
-        )?;
-        if !arg.dry {
-            make_link(&mut detail_pack, arg)?;
+/// How a destination claimed by several packages is settled for one of them
+enum Verdict {
+    /// Wins over the packages
+    Won(Vec<String>),
+    /// Loses to the package
+    Lost(String),
+    /// Neither wins against the packages
+    Tied(Vec<String>),
+}
+
+/// How many destinations of the package are already in place, out of all it would make
+pub(crate) fn link_progress(
+    pkg_name: &str,
+    manifest: &Manifest,
+    vars: &Vars,
+    ledger: &LinkLedger,
+) -> eyre::Result<(usize, usize)> {
+    let detail_pack = PkgPlan::prob(pkg_name, manifest, vars, ledger)?
+        .map(|plan| plan.detail_pack)
+        .unwrap_or_default();
+    let made = detail_pack.iter().filter(|pack| pack.is_destination());
+    let in_place = made.clone().filter(|pack| {
+        matches!(
+            pack,
+            LinkDetailPack::AlreadyLinked { .. } | LinkDetailPack::AlreadyCopied { .. }
+        )
+    });
+    Ok((in_place.count(), made.count()))
+}
+
+/// Per package rules deciding how each file gets linked
+struct LinkRules {
+    /// How files are made unless said otherwise
+    mode: LinkMode,
+    /// Files to leave out
+    ignore: Patterns,
+    /// Files to copy instead of symlink
+    copy: Patterns,
+    /// Variables to render templates with
+    vars: Vars,
+    /// The machine variants are picked for
+    host: Host,
+}
+
+impl LinkRules {
+    fn for_pkg(pkg_name: &str, manifest: &Manifest, vars: &Vars) -> eyre::Result<Self> {
+        Ok(Self {
+            mode: manifest.mode,
+            ignore: Patterns::ignore_for(pkg_name, manifest)?,
+            copy: Patterns::copy_for(pkg_name, manifest)?,
+            vars: vars.clone(),
+            host: Host::current(),
+        })
+    }
+
+    /// How the given source file should be made at its destination
+    fn mode_of(&self, src: &Path) -> LinkMode {
+        if is_template(src) {
+            LinkMode::Template
+        } else if self.copy.matches(src) {
+            LinkMode::Copy
+        } else {
+            self.mode
         }
-        summary.details.push(LinkDetail {
-            pkg_name: pkg_name.to_owned(),
-            detail_pack,
-        });
     }
-    Ok(summary)
+
+    /// Whether the given source dir must be linked entry by entry rather than as a whole
+    fn must_descend(&self, src: &Path) -> io::Result<bool> {
+        Ok(self.mode != LinkMode::Symlink
+            || self.ignore.matches_within(src)?
+            || self.copy.matches_within(src)?
+            || has_template_within(src)?)
+    }
+}
+
+/// The destination of a source file in the target path, templates lose their extension
+pub(crate) fn dest_for(src: &Path, target: &Path) -> eyre::Result<PathBuf> {
+    let name = src
+        .file_name()
+        .ok_or_else(|| LinkError::ImpossibleNamelessPath(src.to_owned()))?
+        .to_string_lossy();
+    let name = Path::new(base_name(&name));
+    let name = if is_template(name) {
+        name.file_stem()
+    } else {
+        name.file_name()
+    };
+    Ok(target.join(name.ok_or_else(|| LinkError::ImpossibleNamelessPath(src.to_owned()))?))
 }
 
 /// Test if the files in the given path can be linked to the target path
-fn prob_link(path: &Path, target: &Path) -> eyre::Result<Vec<LinkDetailPack>> {
-    let mut detail_pack = Vec::new();
+fn prob_link(
+    path: &Path,
+    target: &Path,
+    rules: &LinkRules,
+    ledger: &LinkLedger,
+) -> eyre::Result<Vec<LinkDetailPack>> {
+    let mut entries = Vec::new();
     for file_entry in path.read_dir()? {
-        let file_entry = file_entry?;
-        let src = file_entry.path();
-        let dest = target.join(
-            src.file_name()
-                .ok_or_else(|| LinkError::ImpossibleNamelessPath(src.clone()))?,
-        );
+        entries.push(file_entry?.path());
+    }
+
+    let mut detail_pack = Vec::new();
+    for pick in variant::pick(entries, &rules.host) {
+        let src = match pick {
+            Pick::Plain(src) => src,
+            Pick::Chosen {
+                src_path,
+                reason,
+                others,
+            } => {
+                detail_pack.push(LinkDetailPack::VariantChosen {
+                    dest_path: dest_for(&src_path, target)?,
+                    src_path: src_path.clone(),
+                    reason,
+                    others,
+                });
+                src_path
+            }
+            Pick::NoMatch {
+                src_path,
+                candidates,
+            } => {
+                // No variant is meant for this machine, leave it out
+                detail_pack.push(LinkDetailPack::NoVariant {
+                    dest_path: dest_for(&src_path, target)?,
+                    candidates,
+                });
+                continue;
+            }
+        };
+        let dest = dest_for(&src, target)?;
+
+        if rules.ignore.matches(&src) {
+            // Source is ignored, leave it out
+            detail_pack.push(LinkDetailPack::Ignored { src_path: src });
+            continue;
+        }
+        if !src.is_dir() {
+            let mode = rules.mode_of(&src);
+            if mode != LinkMode::Symlink {
+                // Source is to be copied or rendered, compare contents instead of links
+                detail_pack.push(prob_write(src, dest, mode, rules, ledger)?);
+                continue;
+            }
+        }
 
-        if !dest
+        if dest.is_symlink() && !dest.exists() {
+            // Destination is a symlink to nothing, replace it
+            detail_pack.push(LinkDetailPack::DestOccupied {
+                occupant: Occupant::of(&dest),
+                src_path: src,
+                dest_path: dest,
+                mode: LinkMode::Symlink,
+                force_linked: None,
+                backup_path: None,
+            });
+        } else if !dest
             .try_exists()
             .map_err(|e| LinkError::CannotConfirmFileExistence(dest.to_owned(), e))?
         {
+            if src.is_dir() && rules.must_descend(&src)? {
+                // Source dir holds entries not to be symlinked, link its content instead
+                detail_pack.append(&mut prob_link(&src, &dest, rules, ledger)?);
+                continue;
+            }
             // Destination doesn't exist, just link
             detail_pack.push(LinkDetailPack::Linkable {
                 src_path: src,
@@ -305,15 +1131,22 @@ fn prob_link(path: &Path, target: &Path) -> eyre::Result<Vec<LinkDetailPack>> {
                 src_path: src,
                 dest_path: dest,
             });
-        } else if dest.is_dir() && src.is_dir() {
-            // Destination and Source both dir, recursive link
-            detail_pack.append(&mut prob_link(&src, &dest)?);
+        } else if dest.is_dir()
+            && src.is_dir()
+            && !(dest.is_symlink()
+                && matches!(Occupant::of(&dest), Occupant::LinkedToOtherPackage { .. }))
+        {
+            // Destination and Source both dir, recursive link, unless that links into a package
+            detail_pack.append(&mut prob_link(&src, &dest, rules, ledger)?);
         } else {
-            // Destination exists but not dir, fail
+            // Destination is something else, fail
             detail_pack.push(LinkDetailPack::DestOccupied {
+                occupant: Occupant::of(&dest),
                 src_path: src,
                 dest_path: dest,
+                mode: LinkMode::Symlink,
                 force_linked: None,
+                backup_path: None,
             });
         }
     }
@@ -321,8 +1154,114 @@ fn prob_link(path: &Path, target: &Path) -> eyre::Result<Vec<LinkDetailPack>> {
     Ok(detail_pack)
 }
 
-/// Make a link for the files in the given path
-fn make_link(packs: &mut Vec<LinkDetailPack>, arg: &LinkArg) -> eyre::Result<()> {
+/// The content the destination of a copied or rendered source should have
+fn content_of(src: &Path, mode: LinkMode, rules: &LinkRules) -> Result<Vec<u8>, TemplateError> {
+    match mode {
+        LinkMode::Template => Ok(render(src, &rules.vars)?.into_bytes()),
+        _ => fs::read(src).map_err(|e| TemplateError::CannotRead(src.to_owned(), e)),
+    }
+}
+
+/// Test if the file can be copied or rendered to the destination, comparing against the record
+fn prob_write(
+    src: PathBuf,
+    dest: PathBuf,
+    mode: LinkMode,
+    rules: &LinkRules,
+    ledger: &LinkLedger,
+) -> eyre::Result<LinkDetailPack> {
+    let src_hash = match content_of(&src, mode, rules) {
+        Ok(content) => bytes_hash(&content),
+        Err(e) => {
+            // Source cannot be read or rendered, fail this file only
+            return Ok(LinkDetailPack::Copyable {
+                src_path: src,
+                dest_path: dest,
+                mode,
+                copied: Some(Err(io::Error::other(e))),
+            });
+        }
+    };
+    if !dest
+        .try_exists()
+        .map_err(|e| LinkError::CannotConfirmFileExistence(dest.to_owned(), e))?
+        || dest.is_symlink() && dest.read_link().is_ok_and(|path| path == src)
+    {
+        // Destination doesn't exist or is our own symlink, just write
+        return Ok(LinkDetailPack::Copyable {
+            src_path: src,
+            dest_path: dest,
+            mode,
+            copied: None,
+        });
+    }
+    if dest.is_symlink() || !dest.is_file() {
+        // Destination is something else entirely, fail
+        return Ok(LinkDetailPack::DestOccupied {
+            occupant: Occupant::of(&dest),
+            src_path: src,
+            dest_path: dest,
+            mode,
+            force_linked: None,
+            backup_path: None,
+        });
+    }
+
+    let dest_hash = content_hash(&dest)?;
+    if src_hash == dest_hash {
+        // Destination has the same content, already written
+        return Ok(LinkDetailPack::AlreadyCopied {
+            src_path: src,
+            dest_path: dest,
+            mode,
+        });
+    }
+
+    let record = ledger
+        .find(&dest)
+        .filter(|record| record.mode == mode && record.src_path == src);
+    Ok(match record {
+        Some(record) if record.hash.as_ref() == Some(&dest_hash) => {
+            // Destination is untouched since written, only source changed
+            LinkDetailPack::CopySourceChanged {
+                src_path: src,
+                dest_path: dest,
+                mode,
+                copied: None,
+            }
+        }
+        Some(_) => {
+            // Destination was edited since written, fail
+            LinkDetailPack::CopyDestEdited {
+                src_path: src,
+                dest_path: dest,
+                mode,
+                force_copied: None,
+                backup_path: None,
+            }
+        }
+        None => {
+            // Destination was not written by us, fail
+            LinkDetailPack::DestOccupied {
+                occupant: Occupant::OccupiedByFile,
+                src_path: src,
+                dest_path: dest,
+                mode,
+                force_linked: None,
+                backup_path: None,
+            }
+        }
+    })
+}
+
+/// Make a link for the files in the given path, recording them in the ledger
+fn make_link(
+    pkg_name: &str,
+    packs: &mut Vec<LinkDetailPack>,
+    arg: &LinkArg,
+    rules: &LinkRules,
+    ledger: &mut LinkLedger,
+) -> eyre::Result<()> {
     for pack in packs {
         match pack {
             LinkDetailPack::Linkable {
@@ -331,40 +1270,561 @@ fn make_link(packs: &mut Vec<LinkDetailPack>, arg: &LinkArg) -> eyre::Result<()>
                 linked,
             } => {
                 trace!("Linking {:?}", src_path);
-                *linked = Some(unix::fs::symlink(src_path, dest_path));
+                *linked = Some(make_symlink(pkg_name, src_path, dest_path, None, ledger));
             }
             LinkDetailPack::AlreadyLinked {
                 src_path,
                 dest_path,
             } => {
                 trace!("Ignoring already linked {:?} -> {:?}", dest_path, src_path);
+                if ledger.find(dest_path).is_none() {
+                    trace!("Recording untracked link {:?}", dest_path);
+                    ledger.record(LinkRecord::symlink(pkg_name, src_path, dest_path, None));
+                }
+                continue;
+            }
+            LinkDetailPack::Copyable {
+                src_path,
+                dest_path,
+                mode,
+                copied,
+            }
+            | LinkDetailPack::CopySourceChanged {
+                src_path,
+                dest_path,
+                mode,
+                copied,
+            } => {
+                if matches!(copied, Some(Err(_))) {
+                    warn!("Skipping {:?} that cannot be written", src_path);
+                    continue;
+                }
+                trace!("Writing {:?}", src_path);
+                *copied = Some(make_write(
+                    pkg_name, src_path, dest_path, *mode, None, rules, ledger,
+                ));
+            }
+            LinkDetailPack::AlreadyCopied {
+                src_path,
+                dest_path,
+                mode,
+            } => {
+                trace!("Ignoring already written {:?} <- {:?}", dest_path, src_path);
+                let hash = content_hash(dest_path)?;
+                if ledger
+                    .find(dest_path)
+                    .is_none_or(|record| record.hash.as_ref() != Some(&hash))
+                {
+                    trace!("Recording untracked write {:?}", dest_path);
+                    let backup_path = ledger
+                        .find(dest_path)
+                        .and_then(|record| record.backup_path.clone());
+                    ledger.record(LinkRecord::written(
+                        pkg_name,
+                        src_path,
+                        dest_path,
+                        backup_path,
+                        *mode,
+                        hash,
+                    ));
+                }
+                continue;
+            }
+            LinkDetailPack::Ignored { src_path } => {
+                trace!("Ignoring ignored {:?}", src_path);
+                continue;
+            }
+            LinkDetailPack::VariantChosen { .. } | LinkDetailPack::NoVariant { .. } => continue,
+            LinkDetailPack::Overridden { dest_path, by, .. } => {
+                trace!("Leaving {:?} to package `{}`", dest_path, by);
+                continue;
+            }
+            LinkDetailPack::Collision {
+                dest_path, with, ..
+            } => {
+                warn!("Skipping {:?} also claimed by {:?}", dest_path, with);
                 continue;
             }
             LinkDetailPack::DestOccupied {
                 src_path,
                 dest_path,
+                mode,
+                occupant,
                 force_linked,
+                backup_path,
             } => {
-                if arg.force {
-                    if fs::exists(&dest_path).map_err(|e| {
-                        LinkError::CannotConfirmFileExistence(dest_path.to_owned(), e)
-                    })? {
-                        trace!("Moving occupied destination {:?}", dest_path);
-                        let backup_path = dest_path.with_file_name(format!(
-                            "{}-{}.bak",
-                            dest_path.file_name().unwrap_or_default().to_string_lossy(),
-                            Utc::now().format("%Y%m%d%H%M%S")
-                        ));
-                        fs::rename(&dest_path, &backup_path)?;
-                        trace!("Moved occupied destination to {:?}", backup_path);
-                    }
-                    *force_linked = Some(unix::fs::symlink(src_path, dest_path));
+                if occupant.is_replaceable() {
+                    trace!("Replacing {:?}: {}", dest_path, occupant);
+                    *force_linked = Some(make_replaced(
+                        pkg_name, src_path, dest_path, *mode, rules, ledger,
+                    ));
+                } else if arg.force {
+                    let (backed_up, linked) =
+                        make_forced(pkg_name, src_path, dest_path, *mode, rules, ledger);
+                    *force_linked = Some(linked);
+                    *backup_path = backed_up;
                 } else {
                     warn!("Skipping occupied {:?} -> {:?}", dest_path, src_path);
                 }
             }
+            LinkDetailPack::CopyDestEdited {
+                src_path,
+                dest_path,
+                mode,
+                force_copied,
+                backup_path,
+            } => {
+                if arg.force {
+                    let (backed_up, copied) =
+                        make_forced(pkg_name, src_path, dest_path, *mode, rules, ledger);
+                    *force_copied = Some(copied);
+                    *backup_path = backed_up;
+                } else {
+                    warn!("Skipping locally edited {:?} <- {:?}", dest_path, src_path);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Back up the occupied destination and make it anew, giving where the backup went.
+///
+/// A destination that cannot be backed up is left alone and fails on its own.
+fn make_forced(
+    pkg_name: &str,
+    src_path: &Path,
+    dest_path: &Path,
+    mode: LinkMode,
+    rules: &LinkRules,
+    ledger: &mut LinkLedger,
+) -> (Option<PathBuf>, io::Result<()>) {
+    let backup_path = match make_backup(pkg_name, dest_path) {
+        Ok(backup_path) => backup_path,
+        Err(e) => {
+            warn!("Failed to back up {:?}: {}", dest_path, e);
+            return (None, Err(io::Error::other(e.to_string())));
+        }
+    };
+    let made = match mode {
+        LinkMode::Symlink => {
+            make_symlink(pkg_name, src_path, dest_path, backup_path.clone(), ledger)
+        }
+        LinkMode::Copy | LinkMode::Template => make_write(
+            pkg_name,
+            src_path,
+            dest_path,
+            mode,
+            backup_path.clone(),
+            rules,
+            ledger,
+        ),
+    };
+    (backup_path, made)
+}
+
+/// Remove what is at the destination without backup and make it anew
+fn make_replaced(
+    pkg_name: &str,
+    src_path: &Path,
+    dest_path: &Path,
+    mode: LinkMode,
+    rules: &LinkRules,
+    ledger: &mut LinkLedger,
+) -> io::Result<()> {
+    fs::remove_file(dest_path)?;
+    match mode {
+        LinkMode::Symlink => make_symlink(pkg_name, src_path, dest_path, None, ledger),
+        LinkMode::Copy | LinkMode::Template => {
+            make_write(pkg_name, src_path, dest_path, mode, None, rules, ledger)
         }
     }
+}
 
+/// Move an occupied destination into the backup store, if it is there
+fn make_backup(pkg_name: &str, dest_path: &Path) -> eyre::Result<Option<PathBuf>> {
+    if !dest_path.is_symlink()
+        && !fs::exists(dest_path)
+            .map_err(|e| LinkError::CannotConfirmFileExistence(dest_path.to_owned(), e))?
+    {
+        return Ok(None);
+    }
+    trace!("Moving occupied destination {:?}", dest_path);
+    // Saved right away, so the backup is never left unrecorded
+    let mut backups = BackupIndex::load()?;
+    let backup_path = backups.store(pkg_name, dest_path)?;
+    backups.save()?;
+    Ok(Some(backup_path))
+}
+
+/// Symlink the source to the destination and record it, keeping the earlier backup if any
+fn make_symlink(
+    pkg_name: &str,
+    src_path: &Path,
+    dest_path: &Path,
+    backup_path: Option<PathBuf>,
+    ledger: &mut LinkLedger,
+) -> io::Result<()> {
+    if let Some(parent) = dest_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    unix::fs::symlink(src_path, dest_path)?;
+    let backup_path = backup_path.or_else(|| {
+        ledger
+            .find(dest_path)
+            .and_then(|record| record.backup_path.clone())
+    });
+    ledger.record(LinkRecord::symlink(
+        pkg_name,
+        src_path,
+        dest_path,
+        backup_path,
+    ));
     Ok(())
 }
+
+/// Write the copied or rendered source over the destination and record its hash, keeping the
+/// earlier backup if any
+fn make_write(
+    pkg_name: &str,
+    src_path: &Path,
+    dest_path: &Path,
+    mode: LinkMode,
+    backup_path: Option<PathBuf>,
+    rules: &LinkRules,
+    ledger: &mut LinkLedger,
+) -> io::Result<()> {
+    let content = content_of(src_path, mode, rules).map_err(io::Error::other)?;
+    if let Some(parent) = dest_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if dest_path.is_symlink() {
+        // Never write through a symlink into the package
+        fs::remove_file(dest_path)?;
+    }
+    fs::write(dest_path, &content)?;
+    if mode == LinkMode::Copy {
+        fs::set_permissions(dest_path, src_path.metadata()?.permissions())?;
+    }
+    let backup_path = backup_path.or_else(|| {
+        ledger
+            .find(dest_path)
+            .and_then(|record| record.backup_path.clone())
+    });
+    ledger.record(LinkRecord::written(
+        pkg_name,
+        src_path,
+        dest_path,
+        backup_path,
+        mode,
+        bytes_hash(&content),
+    ));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory under the system temp directory, unique to the test
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("dottie-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn rules() -> LinkRules {
+        let none = || Patterns::load(Path::new("/"), &[], &Manifest::default(), "", &[]).unwrap();
+        LinkRules {
+            mode: LinkMode::Symlink,
+            ignore: none(),
+            copy: none(),
+            vars: Vars::default(),
+            host: Host::current(),
+        }
+    }
+
+    #[test]
+    fn bad_template_fails_only_its_file() {
+        let dir = scratch("bad-template");
+        let files = dir.join("files");
+        let target = dir.join("target");
+        fs::create_dir_all(&files).unwrap();
+        fs::create_dir_all(&target).unwrap();
+        fs::write(files.join("good"), "good").unwrap();
+        fs::write(files.join("bad.tmpl"), "{{ nope }}").unwrap();
+
+        let rules = rules();
+        let mut ledger = LinkLedger::default();
+        let mut detail_pack = prob_link(&files, &target, &rules, &ledger).unwrap();
+        let arg = LinkArg {
+            pkgs: Vec::new(),
+            all: false,
+            profile: None,
+            force: false,
+            dry: false,
+            no_deps: false,
+            format: Format::Text,
+        };
+        make_link("pkg", &mut detail_pack, &arg, &rules, &mut ledger).unwrap();
+
+        assert!(target.join("good").is_symlink());
+        assert!(!target.join("bad").exists());
+        let summary = LinkSummary {
+            details: vec![LinkDetail {
+                pkg_name: "pkg".to_owned(),
+                target,
+                detail_pack,
+            }],
+            ..Default::default()
+        };
+        assert_eq!(summary.failures(), vec![Failure::LinkFailed]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// A plan linking each of the relative destinations under `/t`
+    fn plan(pkg_name: &str, priority: i32, overrides: &[&str], dests: &[&str]) -> PkgPlan {
+        PkgPlan {
+            pkg_name: pkg_name.to_owned(),
+            target: PathBuf::from("/t"),
+            rules: rules(),
+            detail_pack: dests
+                .iter()
+                .map(|dest| LinkDetailPack::Linkable {
+                    src_path: Path::new("/src").join(pkg_name).join(dest),
+                    dest_path: Path::new("/t").join(dest),
+                    linked: None,
+                })
+                .collect(),
+            priority,
+            overrides: overrides.iter().map(|name| (*name).to_owned()).collect(),
+        }
+    }
+
+    /// How each pack of the plan ended up, by destination
+    fn outcomes(plan: &PkgPlan) -> Vec<String> {
+        plan.detail_pack
+            .iter()
+            .map(|pack| match pack {
+                LinkDetailPack::Linkable { dest_path, .. } => {
+                    format!("{} linked", dest_path.display())
+                }
+                LinkDetailPack::Overridden { dest_path, by, .. } => {
+                    format!("{} lost to {by}", dest_path.display())
+                }
+                LinkDetailPack::Collision {
+                    dest_path, with, ..
+                } => format!("{} tied with {}", dest_path.display(), with.join(",")),
+                other => format!("{other:?}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn collision_tie_fails_the_link() {
+        let mut plans = [
+            plan("a", 0, &[], &["rc", "a-only"]),
+            plan("b", 0, &[], &["rc"]),
+        ];
+        settle_collisions(&mut plans);
+
+        assert_eq!(
+            outcomes(&plans[0]),
+            ["/t/rc tied with b", "/t/a-only linked"]
+        );
+        assert_eq!(outcomes(&plans[1]), ["/t/rc tied with a"]);
+        let summary = LinkSummary {
+            details: plans
+                .into_iter()
+                .map(|plan| LinkDetail {
+                    pkg_name: plan.pkg_name,
+                    target: plan.target,
+                    detail_pack: plan.detail_pack,
+                })
+                .collect(),
+            ..Default::default()
+        };
+        assert_eq!(summary.failures(), vec![Failure::LinkFailed]);
+        assert_eq!(Failure::LinkFailed.code(), 4);
+    }
+
+    #[test]
+    fn collision_won_by_priority() {
+        let mut plans = [plan("a", 0, &[], &["rc"]), plan("b", 10, &[], &["rc"])];
+        settle_collisions(&mut plans);
+
+        assert_eq!(outcomes(&plans[0]), ["/t/rc lost to b"]);
+        assert_eq!(outcomes(&plans[1]), ["/t/rc linked"]);
+    }
+
+    #[test]
+    fn collision_won_by_override_regardless_of_priority() {
+        let mut plans = [plan("a", 0, &["b"], &["rc"]), plan("b", 10, &[], &["rc"])];
+        settle_collisions(&mut plans);
+
+        assert_eq!(outcomes(&plans[0]), ["/t/rc linked"]);
+        assert_eq!(outcomes(&plans[1]), ["/t/rc lost to a"]);
+    }
+
+    #[test]
+    fn collision_within_claimed_directory() {
+        let mut plans = [
+            plan("a", 1, &[], &[".config/nvim"]),
+            plan("b", 0, &[], &[".config/nvim/init.lua", ".config/git"]),
+        ];
+        settle_collisions(&mut plans);
+
+        assert_eq!(outcomes(&plans[0]), ["/t/.config/nvim linked"]);
+        assert_eq!(
+            outcomes(&plans[1]),
+            [
+                "/t/.config/nvim/init.lua lost to a",
+                "/t/.config/git linked"
+            ]
+        );
+    }
+
+    #[test]
+    fn write_tells_apart_up_to_date_changed_and_edited() {
+        let dir = scratch("write-states");
+        let src = dir.join("src");
+        let dest = dir.join("dest");
+        fs::write(&src, "v1").unwrap();
+        let rules = rules();
+        let mut ledger = LinkLedger::default();
+        let prob = |ledger: &LinkLedger| {
+            prob_write(src.clone(), dest.clone(), LinkMode::Copy, &rules, ledger).unwrap()
+        };
+
+        assert!(matches!(
+            prob(&ledger),
+            LinkDetailPack::Copyable { copied: None, .. }
+        ));
+        make_write(
+            "pkg",
+            &src,
+            &dest,
+            LinkMode::Copy,
+            None,
+            &rules,
+            &mut ledger,
+        )
+        .unwrap();
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "v1");
+        assert!(matches!(
+            prob(&ledger),
+            LinkDetailPack::AlreadyCopied { .. }
+        ));
+
+        fs::write(&src, "v2").unwrap();
+        assert!(matches!(
+            prob(&ledger),
+            LinkDetailPack::CopySourceChanged { copied: None, .. }
+        ));
+        make_write(
+            "pkg",
+            &src,
+            &dest,
+            LinkMode::Copy,
+            None,
+            &rules,
+            &mut ledger,
+        )
+        .unwrap();
+        assert_eq!(
+            ledger.find(&dest).unwrap().hash.as_deref(),
+            Some(bytes_hash(b"v2").as_str())
+        );
+        assert!(matches!(
+            prob(&ledger),
+            LinkDetailPack::AlreadyCopied { .. }
+        ));
+
+        fs::write(&dest, "edited").unwrap();
+        assert!(matches!(
+            prob(&ledger),
+            LinkDetailPack::CopyDestEdited {
+                force_copied: None,
+                ..
+            }
+        ));
+        assert!(matches!(
+            prob(&LinkLedger::default()),
+            LinkDetailPack::DestOccupied {
+                occupant: Occupant::OccupiedByFile,
+                ..
+            }
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn write_replaces_own_symlink_but_not_directory() {
+        let dir = scratch("write-occupants");
+        let src = dir.join("src");
+        let dest = dir.join("dest");
+        fs::write(&src, "content").unwrap();
+        let rules = rules();
+        let ledger = LinkLedger::default();
+
+        unix::fs::symlink(&src, &dest).unwrap();
+        assert!(matches!(
+            prob_write(src.clone(), dest.clone(), LinkMode::Copy, &rules, &ledger).unwrap(),
+            LinkDetailPack::Copyable { copied: None, .. }
+        ));
+        fs::remove_file(&dest).unwrap();
+        fs::create_dir(&dest).unwrap();
+        assert!(matches!(
+            prob_write(src.clone(), dest.clone(), LinkMode::Copy, &rules, &ledger).unwrap(),
+            LinkDetailPack::DestOccupied { .. }
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn write_records_rendered_content() {
+        let dir = scratch("write-template");
+        let src = dir.join("conf.tmpl");
+        let dest = dir.join("conf");
+        fs::write(&src, "editor={{ editor }}").unwrap();
+        let mut rules = rules();
+        rules.vars.set("editor", "nvim");
+        let mut ledger = LinkLedger::default();
+
+        make_write(
+            "pkg",
+            &src,
+            &dest,
+            LinkMode::Template,
+            None,
+            &rules,
+            &mut ledger,
+        )
+        .unwrap();
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "editor=nvim");
+        assert_eq!(
+            ledger.find(&dest).unwrap().hash.as_deref(),
+            Some(bytes_hash(b"editor=nvim").as_str())
+        );
+        assert!(matches!(
+            prob_write(
+                src.clone(),
+                dest.clone(),
+                LinkMode::Template,
+                &rules,
+                &ledger
+            )
+            .unwrap(),
+            LinkDetailPack::AlreadyCopied { .. }
+        ));
+
+        rules.vars.set("editor", "helix");
+        assert!(matches!(
+            prob_write(src, dest, LinkMode::Template, &rules, &ledger).unwrap(),
+            LinkDetailPack::CopySourceChanged { .. }
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,207 @@
+use clap::Args;
+use globset::Glob;
+use owo_colors::OwoColorize;
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::{
+    cli::{
+        format::{self, Format},
+        link::link_progress,
+        run::list_scripts,
+    },
+    dir::{Dir, exists},
+    ledger::LinkLedger,
+    manifest::Manifest,
+    pkg,
+    template::Vars,
+};
+
+#[derive(Debug, Args)]
+pub struct ListArg {
+    /// Only list packages whose name matches the glob
+    #[arg(
+        value_name = "pattern",
+        help = "Only list packages whose name matches the glob, e.g. `nvim*`"
+    )]
+    pub pattern: Option<String>,
+
+    /// Only list packages with all of the tags
+    #[arg(short, long, help = "Only list packages with the tag, may be repeated")]
+    pub tag: Vec<String>,
+
+    /// Also show the target, tags and dependencies of each package
+    #[arg(
+        short,
+        long,
+        help = "Also show the target, tags and dependencies of each package"
+    )]
+    pub long: bool,
+
+    /// Print the packages as JSON
+    #[arg(long, help = "Print the packages as JSON, with every detail")]
+    pub json: bool,
+}
+
+// LYN: Main
+
+pub fn main(arg: &ListArg) -> eyre::Result<()> {
+    let matcher = match &arg.pattern {
+        Some(pattern) => Some(
+            Glob::new(pattern)
+                .map_err(|e| ListError::InvalidPattern(pattern.to_owned(), e))?
+                .compile_matcher(),
+        ),
+        None => None,
+    };
+    let vars = Vars::load()?;
+    let ledger = LinkLedger::load()?;
+
+    let mut listings = Vec::new();
+    for pkg_name in pkg::all()? {
+        if matcher
+            .as_ref()
+            .is_some_and(|matcher| !matcher.is_match(&pkg_name))
+        {
+            continue;
+        }
+        let manifest = Manifest::load(&pkg_name)?;
+        if !arg.tag.iter().all(|tag| manifest.tags.contains(tag)) {
+            continue;
+        }
+        listings.push(PkgListing::of(&pkg_name, manifest, &vars, &ledger)?);
+    }
+
+    if arg.json {
+        format::print_json(&listings, Format::Json)?;
+    } else {
+        display(&listings, arg.long);
+    }
+
+    Ok(())
+}
+
+// LYN: Package Listing
+
+#[derive(Debug, Error)]
+enum ListError {
+    #[error("Invalid package name pattern `{0}`: {1}")]
+    InvalidPattern(String, globset::Error),
+}
+
+#[derive(Debug, Serialize)]
+struct PkgListing {
+    name: String,
+    description: Option<String>,
+    /// Where the package files are linked into
+    target: String,
+    tags: Vec<String>,
+    dependencies: Vec<String>,
+    /// Executable scripts of the package
+    scripts: usize,
+    /// Destinations already in place, out of `linkable`
+    linked: usize,
+    /// Destinations linking the package would make, leaving out ignored files, variants not
+    /// picked and those other packages win
+    linkable: usize,
+    state: LinkState,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum LinkState {
+    /// Every destination is in place
+    Linked,
+    /// Some destinations are in place
+    Partial,
+    /// No destination is in place
+    NotLinked,
+    /// The package has nothing to link
+    NoFiles,
+}
+
+impl PkgListing {
+    fn of(
+        pkg_name: &str,
+        manifest: Manifest,
+        vars: &Vars,
+        ledger: &LinkLedger,
+    ) -> eyre::Result<Self> {
+        let scripts = if exists(Dir::Scripts {
+            pkg_name: pkg_name.to_owned(),
+        })? {
+            list_scripts(pkg_name, &manifest)?.len()
+        } else {
+            0
+        };
+        let (linked, linkable) = link_progress(pkg_name, &manifest, vars, ledger)?;
+        let state = match (linked, linkable) {
+            (_, 0) => LinkState::NoFiles,
+            (0, _) => LinkState::NotLinked,
+            (linked, linkable) if linked == linkable => LinkState::Linked,
+            _ => LinkState::Partial,
+        };
+        Ok(Self {
+            name: pkg_name.to_owned(),
+            target: manifest.target_dir()?.display().to_string(),
+            description: manifest.description,
+            tags: manifest.tags,
+            dependencies: manifest.dependencies,
+            scripts,
+            linked,
+            linkable,
+            state,
+        })
+    }
+}
+
+fn display(listings: &[PkgListing], long: bool) {
+    println!("{}", "Packages:".bold().bright_green());
+    if listings.is_empty() {
+        println!("- No packages found");
+    }
+    for listing in listings {
+        let state = match listing.state {
+            LinkState::Linked => "linked".bright_green().to_string(),
+            LinkState::Partial => {
+                format!("partially linked ({}/{})", listing.linked, listing.linkable)
+                    .yellow()
+                    .to_string()
+            }
+            LinkState::NotLinked => "not linked".bright_red().to_string(),
+            LinkState::NoFiles => "nothing to link".bright_black().to_string(),
+        };
+        println!(
+            "- Package {} {}, {} file(s), {} script(s){}",
+            format!("`{}`", listing.name).yellow(),
+            state,
+            listing.linkable,
+            listing.scripts,
+            listing
+                .description
+                .as_ref()
+                .map(|description| format!(", {description}").bright_black().to_string())
+                .unwrap_or_default(),
+        );
+        if long {
+            println!("  - Target {}", format!("`{}`", listing.target).cyan());
+            if !listing.tags.is_empty() {
+                println!("  - Tags {}", backticked(&listing.tags).cyan());
+            }
+            if !listing.dependencies.is_empty() {
+                println!(
+                    "  - Depends on {}",
+                    backticked(&listing.dependencies).yellow()
+                );
+            }
+        }
+    }
+}
+
+fn backticked(names: &[String]) -> String {
+    names
+        .iter()
+        .map(|name| format!("`{name}`"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -1,10 +1,27 @@
 use clap::Parser;
 
-use crate::{info::InfoArg, link::LinkArg, run::RunArg};
+use crate::{
+    cli::{
+        adopt::AdoptArg, backup::BackupArg, list::ListArg, new::NewArg, profile::ProfileArg,
+        status::StatusArg,
+    },
+    info::InfoArg,
+    link::LinkArg,
+    run::RunArg,
+    unlink::UnlinkArg,
+};
 
+pub mod adopt;
+pub mod backup;
+pub mod format;
 pub mod info;
 pub mod link;
+pub mod list;
+pub mod new;
+pub mod profile;
 pub mod run;
+pub mod status;
+pub mod unlink;
 
 #[derive(Debug, Parser)]
 #[command(version, about)]
@@ -15,6 +32,27 @@ pub enum CliArg {
     #[clap(about = "Link files of packages")]
     Link(LinkArg),
 
+    #[clap(about = "Remove links made for files of packages")]
+    Unlink(UnlinkArg),
+
+    #[clap(about = "List, restore or prune what `link --force` backed up")]
+    Backup(BackupArg),
+
+    #[clap(about = "Report destinations that drifted from what linking would make")]
+    Status(StatusArg),
+
+    #[clap(about = "Move existing files into a package and link them back")]
+    Adopt(AdoptArg),
+
+    #[clap(about = "Create a new package, optionally from a template")]
+    New(NewArg),
+
+    #[clap(about = "List packages with their files, scripts and link state")]
+    List(ListArg),
+
+    #[clap(about = "Show profiles or set the default profile of this machine")]
+    Profile(ProfileArg),
+
     #[clap(about = "Display binary built info")]
     Info(InfoArg),
 }
@@ -0,0 +1,217 @@
+use std::{
+    fs, io,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+use clap::Args;
+use owo_colors::OwoColorize;
+use thiserror::Error;
+
+use crate::{
+    dir::{Dir, SCRIPTS_POSTFIX, ensure_exists, exists, get, is_valid_pkg_name},
+    manifest::MANIFEST_FILE,
+};
+
+#[derive(Debug, Args)]
+pub struct NewArg {
+    /// The name of the package to create
+    #[arg(value_name = "package", help = "The name of the package to create")]
+    pub pkg_name: String,
+
+    /// Also write a manifest to fill in
+    #[arg(
+        short,
+        long,
+        conflicts_with = "template",
+        help = "Also write a manifest to fill in"
+    )]
+    pub manifest: bool,
+
+    /// Also write an executable script stub of the name
+    #[arg(
+        short,
+        long,
+        value_name = "name",
+        num_args = 0..=1,
+        default_missing_value = "install",
+        help = "Also write an executable script stub of the name, `install` if not given"
+    )]
+    pub script: Option<String>,
+
+    /// Start from a copy of the template in `~/.dottie/.templates/`
+    #[arg(
+        short,
+        long = "from-template",
+        value_name = "template",
+        help = "Start from a copy of the template in `~/.dottie/.templates/`"
+    )]
+    pub template: Option<String>,
+}
+
+// LYN: Main
+
+pub fn main(arg: &NewArg) -> eyre::Result<()> {
+    let created = new_pkg(arg)?;
+
+    println!(
+        "{} {}",
+        "Created package".bold().bright_green(),
+        format!("`{}`", arg.pkg_name).yellow()
+    );
+    for path in created {
+        println!("- {}", format!("`{}`", path.display()).cyan());
+    }
+
+    Ok(())
+}
+
+// LYN: Package Scaffolding
+
+#[derive(Debug, Error)]
+enum NewError {
+    #[error("Invalid {0} name `{1}`, it must not be empty, start with `.` or contain `/`")]
+    InvalidName(&'static str, String),
+    #[error("Package `{0}` already exists")]
+    AlreadyExists(String),
+    #[error("Template `{0}` not found at {1}")]
+    TemplateNotFound(String, PathBuf),
+    #[error("Script `{0}` already exists in the template")]
+    ScriptExists(String),
+    #[error("Failed to write {0}: {1}")]
+    CannotWrite(PathBuf, io::Error),
+}
+
+/// Create the package skeleton, giving the paths created
+fn new_pkg(arg: &NewArg) -> eyre::Result<Vec<PathBuf>> {
+    let pkg_name = &arg.pkg_name;
+    if !is_valid_pkg_name(pkg_name) {
+        Err(NewError::InvalidName("package", pkg_name.to_owned()))?;
+    }
+    if exists(Dir::Pkg {
+        pkg_name: pkg_name.to_owned(),
+    })? {
+        Err(NewError::AlreadyExists(pkg_name.to_owned()))?;
+    }
+    if let Some(script_name) = &arg.script
+        && !is_valid_pkg_name(script_name)
+    {
+        Err(NewError::InvalidName("script", script_name.to_owned()))?;
+    }
+    let template = match &arg.template {
+        Some(name) => {
+            if !is_valid_pkg_name(name) {
+                Err(NewError::InvalidName("template", name.to_owned()))?;
+            }
+            let path = get(Dir::Templates).join(name);
+            if !path.is_dir() {
+                Err(NewError::TemplateNotFound(name.to_owned(), path.clone()))?;
+            }
+            Some(path)
+        }
+        None => None,
+    };
+    if let (Some(script_name), Some(template)) = (&arg.script, &template)
+        && template
+            .join(SCRIPTS_POSTFIX)
+            .join(script_name)
+            .symlink_metadata()
+            .is_ok()
+    {
+        Err(NewError::ScriptExists(script_name.to_owned()))?;
+    }
+
+    let pkg_dir = get(Dir::Pkg {
+        pkg_name: pkg_name.to_owned(),
+    });
+    ensure_exists(Dir::Pkg {
+        pkg_name: pkg_name.to_owned(),
+    })?;
+    let mut created = vec![pkg_dir.clone()];
+    // Leave nothing behind on failure, so creating the package can simply be retried
+    if let Err(e) = scaffold(arg, template.as_deref(), &pkg_dir, &mut created) {
+        fs::remove_dir_all(&pkg_dir)?;
+        return Err(e);
+    }
+    Ok(created)
+}
+
+/// Fill the freshly created package directory, adding the paths created
+fn scaffold(
+    arg: &NewArg,
+    template: Option<&Path>,
+    pkg_dir: &Path,
+    created: &mut Vec<PathBuf>,
+) -> eyre::Result<()> {
+    let pkg_name = &arg.pkg_name;
+    if let Some(template) = template {
+        copy_dir(template, pkg_dir, created)?;
+    }
+    for dir in [
+        Dir::Files {
+            pkg_name: pkg_name.to_owned(),
+        },
+        Dir::Scripts {
+            pkg_name: pkg_name.to_owned(),
+        },
+    ] {
+        if !exists(dir.clone())? {
+            created.push(get(dir.clone()));
+            ensure_exists(dir)?;
+        }
+    }
+
+    if arg.manifest {
+        let path = pkg_dir.join(MANIFEST_FILE);
+        fs::write(&path, manifest_stub(pkg_name))
+            .map_err(|e| NewError::CannotWrite(path.clone(), e))?;
+        created.push(path);
+    }
+    if let Some(script_name) = &arg.script {
+        let path = get(Dir::Scripts {
+            pkg_name: pkg_name.to_owned(),
+        })
+        .join(script_name);
+        fs::write(&path, SCRIPT_STUB).map_err(|e| NewError::CannotWrite(path.clone(), e))?;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755))
+            .map_err(|e| NewError::CannotWrite(path.clone(), e))?;
+        created.push(path);
+    }
+    Ok(())
+}
+
+fn manifest_stub(pkg_name: &str) -> String {
+    format!(
+        r#"description = {}
+# target = "~"
+# dependencies = []
+# tags = []
+# mode = "symlink"
+
+# [scripts]
+# order = []
+# once = []
+"#,
+        toml::Value::String(pkg_name.to_owned())
+    )
+}
+
+static SCRIPT_STUB: &str = "#!/bin/sh\nset -eu\n\n";
+
+/// Copy the directory content recursively, keeping permissions
+fn copy_dir(src: &Path, dest: &Path, created: &mut Vec<PathBuf>) -> eyre::Result<()> {
+    for entry in src.read_dir()? {
+        let entry = entry?;
+        let src = entry.path();
+        let dest = dest.join(entry.file_name());
+        if src.is_dir() {
+            fs::create_dir(&dest).map_err(|e| NewError::CannotWrite(dest.clone(), e))?;
+            created.push(dest.clone());
+            copy_dir(&src, &dest, created)?;
+        } else {
+            fs::copy(&src, &dest).map_err(|e| NewError::CannotWrite(dest.clone(), e))?;
+            created.push(dest);
+        }
+    }
+    Ok(())
+}
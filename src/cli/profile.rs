@@ -0,0 +1,75 @@
+use clap::Args;
+use owo_colors::OwoColorize;
+
+use crate::profile::{Machine, Profile};
+
+#[derive(Debug, Args)]
+pub struct ProfileArg {
+    /// The profile to use by default on this machine
+    #[arg(
+        value_name = "profile",
+        help = "The profile to use by default on this machine"
+    )]
+    pub name: Option<String>,
+
+    /// Stop using a default profile on this machine
+    #[arg(
+        short,
+        long,
+        conflicts_with = "name",
+        help = "Stop using a default profile on this machine"
+    )]
+    pub unset: bool,
+}
+
+// LYN: Main
+
+pub fn main(arg: &ProfileArg) -> eyre::Result<()> {
+    let mut machine = Machine::load()?;
+    if let Some(name) = &arg.name {
+        Profile::load(name)?;
+        machine.profile = Some(name.to_owned());
+        machine.save()?;
+        println!(
+            "{} {}",
+            "Default profile set to".bold().bright_green(),
+            format!("`{}`", name).yellow()
+        );
+    } else if arg.unset {
+        machine.profile = None;
+        machine.save()?;
+        println!("{}", "Default profile unset".bold().bright_green());
+    } else {
+        display(machine.profile.as_deref())?;
+    }
+
+    Ok(())
+}
+
+// LYN: Profile List
+
+fn display(default: Option<&str>) -> eyre::Result<()> {
+    println!("{}", "Profiles:".bold().bright_green());
+    let names = Profile::all()?;
+    if names.is_empty() {
+        println!("- No profiles in `~/.dottie/.profiles`");
+    }
+    for name in names {
+        let profile = Profile::load(&name)?;
+        println!(
+            "- Profile {} selects {} package(s){}{}",
+            format!("`{}`", name).yellow(),
+            profile.packages.len(),
+            profile
+                .description
+                .map(|description| format!(", {description}").bright_black().to_string())
+                .unwrap_or_default(),
+            if default == Some(name.as_str()) {
+                " (default)".bright_green().to_string()
+            } else {
+                String::new()
+            },
+        );
+    }
+    Ok(())
+}
@@ -1,20 +1,33 @@
 use std::{
+    cmp::Ordering,
     ffi::OsString,
-    os::unix::fs::MetadataExt,
+    io, mem,
+    os::{fd::AsFd, unix::fs::MetadataExt},
     path::PathBuf,
-    process::{Command, ExitStatus},
-    sync::{Arc, Barrier, Mutex},
-    thread,
+    process::{Command, ExitCode, ExitStatus, Stdio},
+    time::Duration,
 };
 
+use chrono::{DateTime, Local, Utc};
 use clap::Args;
-use log::{error, info, trace, warn};
-use nix::libc::pid_t;
+use log::{info, trace, warn};
 use owo_colors::OwoColorize;
-use signal_hook::{consts::SIGINT, iterator::Signals};
+use serde::Serialize;
 use thiserror::Error;
 
-use crate::dir::{Dir, exists, get, not_package};
+use crate::{
+    cli::format::{self, Format, Record},
+    condition::{Condition, first_unmet},
+    dir::{Dir, exists, get},
+    duration,
+    exit::{self, Failure},
+    host::Host,
+    ledger::{RunLedger, content_hash},
+    manifest::{Manifest, Rerun},
+    pkg::{self, DepIssue, Selection},
+    process::{Supervisor, Waited},
+    variant::suffix_of,
+};
 
 // LYN: Arguments
 
@@ -24,7 +37,6 @@ pub struct RunArg {
     #[arg(
         value_name = "packages",
         group = "specify",
-        required = true,
         help = "The package(s) whose scripts will be run"
     )]
     pub pkgs: Vec<String>,
@@ -33,6 +45,15 @@ pub struct RunArg {
     #[arg(short, long, group = "specify", help = "Run scripts for all packages")]
     pub all: bool,
 
+    /// Run scripts for the packages of a profile, defaults to the profile of this machine
+    #[arg(
+        short,
+        long,
+        group = "specify",
+        help = "Run scripts for the packages of a profile, defaults to the profile of this machine"
+    )]
+    pub profile: Option<String>,
+
     /// Dry run mode, only prints what scripts would be run
     #[arg(
         short,
@@ -40,37 +61,117 @@ pub struct RunArg {
         help = "Dry run mode, only print what scripts would be run"
     )]
     pub dry: bool,
+
+    /// Run run-once and run-on-change scripts even if they already ran
+    #[arg(
+        long,
+        help = "Run run-once and run-on-change scripts even if they already ran"
+    )]
+    pub rerun: bool,
+
+    /// Stop scripts running longer than this, unless the manifest gives them their own timeout
+    #[arg(
+        short,
+        long,
+        value_parser = duration::parse,
+        help = "Stop scripts running longer than this (e.g. `90s`, `1h30m`), unless the manifest gives them their own timeout"
+    )]
+    pub timeout: Option<Duration>,
+
+    /// Do not pull in the dependencies of the named packages
+    #[arg(long, help = "Do not pull in the dependencies of the named packages")]
+    pub no_deps: bool,
+
+    /// Skip all remaining scripts of the run after the first script that fails
+    #[arg(
+        long,
+        conflicts_with = "keep_going",
+        help = "Skip all remaining scripts of the run after the first script that fails"
+    )]
+    pub fail_fast: bool,
+
+    /// Keep running the remaining scripts after one fails, even in packages set to fail fast
+    #[arg(
+        long,
+        help = "Keep running the remaining scripts after one fails, even in packages set to fail fast"
+    )]
+    pub keep_going: bool,
+
+    /// How the summary is printed
+    #[arg(
+        long,
+        value_enum,
+        default_value_t,
+        help = "How the summary is printed, script output goes to stderr unless `text`"
+    )]
+    pub format: Format,
 }
 
 // LYN: Main
 
-pub fn main(arg: &RunArg) -> eyre::Result<()> {
-    let summary = if arg.all {
-        run_all(arg)?
-    } else {
-        run_specified(arg)?
-    };
+pub fn main(arg: &RunArg) -> eyre::Result<ExitCode> {
+    let selection = pkg::select(&arg.pkgs, arg.all, arg.profile.as_deref(), !arg.no_deps)?;
+    let mut summary = run_selected(arg, selection)?;
+    let code = exit::code_of(summary.failures());
 
-    summary.display()?;
+    match arg.format {
+        Format::Text => summary.display()?,
+        Format::Json => format::print_json(&summary, arg.format)?,
+        Format::Ndjson => {
+            // Packages were printed as their scripts finished
+            summary.statuses.clear();
+            format::print_json(&Record::<RunStatus, _>::Summary(&summary), arg.format)?;
+        }
+    }
 
-    Ok(())
+    Ok(code)
 }
 
 // LYN: Run Summary
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Serialize)]
 struct RunSummary {
+    /// The profile the packages were selected by
+    profile: Option<String>,
     /// Packages whose scripts were ran and their statuses
+    #[serde(skip_serializing_if = "Vec::is_empty")]
     statuses: Vec<RunStatus>,
     /// package names that do not exist
     non_exist: Vec<String>,
     /// package names that do not have scripts
     no_scripts: Vec<String>,
+    /// Packages skipped because of their dependencies
+    dep_issues: Vec<DepIssue>,
+    /// Whether Ctrl-C stopped the run before all scripts ran
+    aborted: bool,
 }
 
 impl RunSummary {
+    /// What went wrong, deciding the exit code
+    fn failures(&self) -> Vec<Failure> {
+        let mut failures = Vec::new();
+        if !self.non_exist.is_empty() || !self.dep_issues.is_empty() {
+            failures.push(Failure::PackageMissing);
+        }
+        if self
+            .statuses
+            .iter()
+            .flat_map(|status| &status.status_pack)
+            .any(|pack| pack.state.failed())
+        {
+            failures.push(Failure::ScriptFailed);
+        }
+        if self.aborted {
+            failures.push(Failure::Interrupted);
+        }
+        failures
+    }
+
     fn display(&self) -> eyre::Result<()> {
         println!("{}", "Run Summary:".bold().bright_green());
+        if let Some(profile) = &self.profile {
+            println!("- Using profile {}", format!("`{}`", profile).yellow());
+        }
 
         for status in &self.statuses {
             println!(
@@ -78,25 +179,64 @@ impl RunSummary {
                 format!("`{}`", status.pkg_name).yellow(),
                 status.status_pack.len()
             );
+            let mut position = 0;
             for pack in &status.status_pack {
-                if let Some(status) = &pack.status {
-                    if status.success() {
+                match &pack.state {
+                    ScriptState::Finished(status) if status.success() => {
                         println!(
                             "  - {} finished successfully",
                             format!("`{}`", pack.script_name).cyan()
                         );
-                    } else {
+                    }
+                    ScriptState::Finished(status) => {
                         println!(
                             "  - {} finished with {}",
                             format!("`{}`", pack.script_name).cyan(),
                             status.bright_red()
                         );
                     }
-                } else {
-                    println!(
-                        "  - {} would be ran",
-                        format!("`{}`", pack.script_name).cyan()
-                    );
+                    ScriptState::TimedOut { after, killed } => {
+                        println!(
+                            "  - {} timed out after {}, {}",
+                            format!("`{}`", pack.script_name).cyan(),
+                            duration::display(*after).bright_red(),
+                            if *killed { "killed" } else { "terminated" }
+                        );
+                    }
+                    ScriptState::Planned => {
+                        position += 1;
+                        println!(
+                            "  - {} {} would be ran",
+                            format!("{position}.").bright_black(),
+                            format!("`{}`", pack.script_name).cyan()
+                        );
+                    }
+                    ScriptState::AlreadyRan { ran_at, unchanged } => {
+                        println!(
+                            "  - {} skipped, {}",
+                            format!("`{}`", pack.script_name).cyan(),
+                            format!(
+                                "already ran at {}{}",
+                                ran_at.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S"),
+                                if *unchanged { ", unchanged since" } else { "" }
+                            )
+                            .bright_black()
+                        );
+                    }
+                    ScriptState::NotRun => {
+                        println!(
+                            "  - {} {}",
+                            format!("`{}`", pack.script_name).cyan(),
+                            "not run due to earlier failure".bright_black()
+                        );
+                    }
+                    ScriptState::ConditionNotMet(reason) => {
+                        println!(
+                            "  - {} skipped {}",
+                            format!("`{}`", pack.script_name).cyan(),
+                            format!("(condition not met: {reason})").bright_black()
+                        );
+                    }
                 }
             }
         }
@@ -112,11 +252,20 @@ impl RunSummary {
                 format!("`{}`", pkg_name).yellow()
             );
         }
+        for issue in &self.dep_issues {
+            println!("- {}", issue);
+        }
+        if self.aborted {
+            println!(
+                "- {} by Ctrl-C, the remaining scripts were not run",
+                "Aborted".bold().on_red()
+            );
+        }
         Ok(())
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 struct RunStatus {
     /// The name of the package whose scripts were ran
     pkg_name: String,
@@ -124,12 +273,47 @@ struct RunStatus {
     status_pack: Vec<RunStatusPack>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 struct RunStatusPack {
     /// The name of the script that was run
     script_name: String,
-    /// The status of the script, if not dry run
-    status: Option<ExitStatus>,
+    /// What became of the script
+    state: ScriptState,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", content = "detail", rename_all = "snake_case")]
+enum ScriptState {
+    /// Would be run, in dry run mode
+    Planned,
+    /// Ran and exited with the status
+    Finished(#[serde(serialize_with = "format::exit_status")] ExitStatus),
+    /// Not run, the reason tells which condition does not hold on this machine
+    ConditionNotMet(String),
+    /// Stopped for running longer than its timeout, `killed` if SIGTERM was not enough
+    TimedOut {
+        #[serde(rename = "after_secs", serialize_with = "format::seconds")]
+        after: Duration,
+        killed: bool,
+    },
+    /// Not run again, it is run-once or unchanged since it last succeeded
+    AlreadyRan {
+        ran_at: DateTime<Utc>,
+        unchanged: bool,
+    },
+    /// Not run, an earlier script failed in fail-fast mode
+    NotRun,
+}
+
+impl ScriptState {
+    /// Whether the script ran and did not succeed
+    fn failed(&self) -> bool {
+        match self {
+            ScriptState::Finished(status) => !status.success(),
+            ScriptState::TimedOut { .. } => true,
+            _ => false,
+        }
+    }
 }
 
 // LYN: Run Scripts
@@ -142,19 +326,22 @@ enum RunError {
     ImpossibleNamelessPath(PathBuf),
 }
 
-/// Runs scripts for all packages.
-fn run_all(arg: &RunArg) -> eyre::Result<RunSummary> {
-    let mut summary = RunSummary::default();
-    for pkg_entry in get(Dir::App).read_dir()? {
-        let pkg_entry = pkg_entry?;
-        if not_package(&pkg_entry.path()) {
-            info!("Skipping non backage entry: {:?}", pkg_entry);
-            continue;
+/// Runs scripts for the selected packages, dependencies first.
+fn run_selected(arg: &RunArg, mut selection: Selection) -> eyre::Result<RunSummary> {
+    let mut summary = RunSummary {
+        profile: selection.profile.clone(),
+        non_exist: mem::take(&mut selection.non_exist),
+        dep_issues: mem::take(&mut selection.dep_issues),
+        ..Default::default()
+    };
+    let mut ledger = RunLedger::load()?;
+    let supervisor = Supervisor::start()?;
+    let mut failed = false;
+    for pkg_name in &selection.ordered {
+        if supervisor.aborted() {
+            summary.aborted = true;
+            break;
         }
-        let pkg_name = pkg_entry
-            .file_name()
-            .into_string()
-            .map_err(RunError::InvalidUtf8OsString)?;
         if !exists(Dir::Scripts {
             pkg_name: pkg_name.to_owned(),
         })? {
@@ -162,68 +349,152 @@ fn run_all(arg: &RunArg) -> eyre::Result<RunSummary> {
             warn!("Package `{}` does not have a scripts folder", pkg_name);
             continue;
         }
-        summary.statuses.push(RunStatus {
-            pkg_name: pkg_name.clone(),
-            status_pack: run_pack(&pkg_name, arg.dry)?,
-        });
+        let status_pack = run_pack(
+            pkg_name,
+            &selection.manifest(pkg_name)?,
+            arg,
+            &mut ledger,
+            &supervisor,
+            arg.fail_fast && failed,
+        )?;
+        failed |= status_pack.iter().any(|pack| pack.state.failed());
+        let status = RunStatus {
+            pkg_name: pkg_name.to_owned(),
+            status_pack,
+        };
+        if arg.format == Format::Ndjson {
+            format::print_json(&Record::<_, RunSummary>::Package(&status), arg.format)?;
+        }
+        summary.statuses.push(status);
     }
-
+    summary.aborted |= supervisor.aborted();
     Ok(summary)
 }
 
-/// Runs scripts for specified packages.
-fn run_specified(arg: &RunArg) -> eyre::Result<RunSummary> {
-    let mut summary = RunSummary::default();
-    for pkg_name in &arg.pkgs {
-        if !exists(Dir::Pkg {
-            pkg_name: pkg_name.to_owned(),
-        })? {
-            summary.non_exist.push(pkg_name.to_owned());
-            warn!("Package `{}` does not exist", pkg_name);
+/// Runs scripts for a specific package, optionally in dry run mode.
+///
+/// Once a script fails in fail-fast mode the remaining ones are not run, `halted` tells a script
+/// of an earlier package already failed in global fail-fast mode.
+fn run_pack(
+    pkg_name: &str,
+    manifest: &Manifest,
+    arg: &RunArg,
+    ledger: &mut RunLedger,
+    supervisor: &Supervisor,
+    mut halted: bool,
+) -> eyre::Result<Vec<RunStatusPack>> {
+    let host = Host::current();
+    let fail_fast = !arg.keep_going && (arg.fail_fast || manifest.scripts.fail_fast);
+    let mut status_pack = Vec::new();
+    for (script_name, path) in list_scripts(pkg_name, manifest)? {
+        if supervisor.aborted() {
+            break;
+        }
+        if halted {
+            status_pack.push(RunStatusPack {
+                script_name,
+                state: ScriptState::NotRun,
+            });
             continue;
         }
-        if !exists(Dir::Scripts {
-            pkg_name: pkg_name.to_owned(),
-        })? {
-            summary.no_scripts.push(pkg_name.to_owned());
-            warn!("Package `{}` does not have a scripts folder", pkg_name);
+        if let Some(reason) = unmet_condition(&script_name, manifest, &host) {
+            info!("Skipping script `{}`: {}", script_name, reason);
+            status_pack.push(RunStatusPack {
+                script_name,
+                state: ScriptState::ConditionNotMet(reason),
+            });
             continue;
         }
-        summary.statuses.push(RunStatus {
-            pkg_name: pkg_name.to_owned(),
-            status_pack: run_pack(pkg_name, arg.dry)?,
-        });
-    }
-    Ok(summary)
-}
 
-/// Runs scripts for a specific package, optionally in dry run mode.
-fn run_pack(pkg_name: &str, dry: bool) -> eyre::Result<Vec<RunStatusPack>> {
-    let child_slot: Arc<Mutex<Option<u32>>> = Arc::new(Mutex::new(None));
-    let mut signals = Signals::new([SIGINT])?;
-    let handle = signals.handle();
-    let killer_start = Arc::new(Barrier::new(2));
-    {
-        let child_slot = Arc::clone(&child_slot);
-        let barrier = Arc::clone(&killer_start);
-        thread::spawn(move || {
-            let id = thread::current().id();
-            info!("Child killer {:?} started", id);
-            barrier.wait();
-            for _ in signals.forever() {
-                if let Some(child_pid) = child_slot.lock().unwrap().take() {
-                    let _ = nix::sys::signal::kill(
-                        nix::unistd::Pid::from_raw(child_pid as pid_t),
-                        nix::sys::signal::Signal::SIGKILL,
-                    );
+        let rerun = manifest.scripts.rerun_of(&script_name);
+        let hash = content_hash(&path)?;
+        if !arg.rerun
+            && let Some(record) = ledger.find(pkg_name, &script_name)
+            && match rerun {
+                Rerun::Always => false,
+                Rerun::Never => true,
+                Rerun::OnChange => record.hash == hash,
+            }
+        {
+            info!("Skipping script `{}` already ran", script_name);
+            status_pack.push(RunStatusPack {
+                script_name,
+                state: ScriptState::AlreadyRan {
+                    ran_at: record.ran_at,
+                    unchanged: rerun == Rerun::OnChange,
+                },
+            });
+            continue;
+        }
+
+        trace!(
+            "Executing script `{}` for package `{}`",
+            script_name, pkg_name
+        );
+
+        let state = if arg.dry {
+            ScriptState::Planned
+        } else {
+            let stdout = if arg.format.is_machine() {
+                // Keep stdout for the summary alone
+                Stdio::from(io::stderr().as_fd().try_clone_to_owned()?)
+            } else {
+                println!(
+                    "{}",
+                    format!(
+                        "Executing script {} for package {}",
+                        format!("`{}`", script_name).black(),
+                        format!("`{}`", pkg_name).yellow()
+                    )
+                    .on_blue()
+                );
+                Stdio::inherit()
+            };
+            let timeout = manifest.scripts.timeout_of(&script_name).or(arg.timeout);
+            let waited = supervisor.run(
+                Command::new(&path)
+                    .envs(&manifest.scripts.env)
+                    .stdin(Stdio::inherit())
+                    .stdout(stdout)
+                    .stderr(Stdio::inherit()),
+                timeout,
+            )?;
+
+            match waited {
+                Waited::Exited(status) => {
+                    if status.success() && rerun != Rerun::Always {
+                        ledger.record(pkg_name, &script_name, hash);
+                        ledger.save()?;
+                    }
+                    ScriptState::Finished(status)
+                }
+                Waited::TimedOut { after, killed } => {
+                    warn!("Script `{}` timed out after {:?}", script_name, after);
+                    ScriptState::TimedOut { after, killed }
                 }
             }
-            info!("Child killer {:?} suicided", id);
-        });
+        };
+        trace!("Script finished with state: {:?}", state);
+        if fail_fast && state.failed() {
+            warn!(
+                "Script `{}` failed, not running the remaining scripts",
+                script_name
+            );
+            halted = true;
+        }
+        status_pack.push(RunStatusPack { script_name, state });
     }
 
-    killer_start.wait();
-    let mut status_pack = Vec::new();
+    Ok(status_pack)
+}
+
+/// Executable scripts of the package in the order they run: those listed in the manifest `order`
+/// first, then the rest sorted naturally by name
+pub(crate) fn list_scripts(
+    pkg_name: &str,
+    manifest: &Manifest,
+) -> eyre::Result<Vec<(String, PathBuf)>> {
+    let mut scripts = Vec::new();
     for entry in get(Dir::Scripts {
         pkg_name: pkg_name.to_owned(),
     })
@@ -242,43 +513,102 @@ fn run_pack(pkg_name: &str, dry: bool) -> eyre::Result<Vec<RunStatusPack>> {
             .to_owned()
             .into_string()
             .map_err(RunError::InvalidUtf8OsString)?;
+        scripts.push((script_name, path));
+    }
 
-        trace!(
-            "Executing script `{}` for package `{}`",
-            script_name, pkg_name
-        );
+    scripts.sort_by(|(a, _), (b, _)| natural_cmp(a, b));
+    let position = |script_name: &str| {
+        manifest
+            .scripts
+            .order
+            .iter()
+            .position(|ordered| ordered == script_name)
+            .unwrap_or(usize::MAX)
+    };
+    scripts.sort_by_key(|(script_name, _)| position(script_name));
+    for ordered in &manifest.scripts.order {
+        if !scripts
+            .iter()
+            .any(|(script_name, _)| script_name == ordered)
+        {
+            warn!(
+                "Script `{}` in `order` is not an executable script",
+                ordered
+            );
+        }
+    }
+    Ok(scripts)
+}
 
-        let status = if dry {
-            None
-        } else {
-            println!(
-                "{}",
-                format!(
-                    "Executing script {} for package {}",
-                    format!("`{}`", script_name).black(),
-                    format!("`{}`", pkg_name).yellow()
-                )
-                .on_blue()
+/// Compare names with runs of digits compared by value, so `9-a` comes before `10-b`
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        let (Some(ca), Some(cb)) = (a.chars().next(), b.chars().next()) else {
+            return a.len().cmp(&b.len());
+        };
+        let ordering = if ca.is_ascii_digit() && cb.is_ascii_digit() {
+            let a_end = a.find(|c: char| !c.is_ascii_digit()).unwrap_or(a.len());
+            let b_end = b.find(|c: char| !c.is_ascii_digit()).unwrap_or(b.len());
+            let (a_digits, b_digits) = (
+                a[..a_end].trim_start_matches('0'),
+                b[..b_end].trim_start_matches('0'),
             );
-            let mut child = Command::new(&path)
-                .stdin(std::process::Stdio::inherit())
-                .stdout(std::process::Stdio::inherit())
-                .stderr(std::process::Stdio::inherit())
-                .spawn()?;
+            let ordering = a_digits
+                .len()
+                .cmp(&b_digits.len())
+                .then_with(|| a_digits.cmp(b_digits))
+                .then_with(|| a_end.cmp(&b_end));
+            (a, b) = (&a[a_end..], &b[b_end..]);
+            ordering
+        } else {
+            (a, b) = (&a[ca.len_utf8()..], &b[cb.len_utf8()..]);
+            ca.cmp(&cb)
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
 
-            *child_slot.lock().unwrap() = Some(child.id());
-            let status = child.wait()?;
-            *child_slot.lock().unwrap() = None;
+/// Describe the first condition of the script that does not hold, from its name or the manifest
+fn unmet_condition(script_name: &str, manifest: &Manifest, host: &Host) -> Option<String> {
+    let mut conditions = match suffix_of(script_name).map(Condition::parse_list) {
+        Some(Ok(conditions)) => conditions,
+        Some(Err(e)) => return Some(e.to_string()),
+        None => Vec::new(),
+    };
+    conditions.extend(manifest.scripts.conditions_of(script_name));
+    first_unmet(&conditions, host).map(Condition::to_string)
+}
 
-            Some(status)
-        };
-        trace!("Script finished with status: {:?}", status);
-        status_pack.push(RunStatusPack {
-            script_name,
-            status,
-        });
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn natural_cmp_orders_digit_runs_by_value() {
+        assert_eq!(natural_cmp("a9", "a10"), Ordering::Less);
+        assert_eq!(natural_cmp("a10", "a9"), Ordering::Greater);
+        assert_eq!(natural_cmp("9-a", "10-b"), Ordering::Less);
+        assert_eq!(natural_cmp("02-x", "10-x"), Ordering::Less);
     }
 
-    handle.close();
-    Ok(status_pack)
+    #[test]
+    fn natural_cmp_breaks_ties() {
+        assert_eq!(natural_cmp("a1", "a01"), Ordering::Less);
+        assert_eq!(natural_cmp("a", "a1"), Ordering::Less);
+        assert_eq!(natural_cmp("a1b", "a1c"), Ordering::Less);
+        assert_eq!(natural_cmp("same10", "same10"), Ordering::Equal);
+    }
+
+    #[test]
+    fn natural_cmp_sorts_scripts() {
+        let mut names = vec!["10-last", "2-second", "1-first", "setup", "01-zero"];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(
+            names,
+            ["1-first", "01-zero", "2-second", "10-last", "setup"]
+        );
+    }
 }
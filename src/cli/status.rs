@@ -0,0 +1,332 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    mem,
+    path::PathBuf,
+    process::ExitCode,
+};
+
+use clap::Args;
+use owo_colors::OwoColorize;
+
+use crate::{
+    cli::link::{LinkDetailPack, Occupant, PkgPlan, settle_collisions},
+    exit::{self, Failure},
+    ledger::{LinkLedger, LinkMode},
+    pkg::{self, DepIssue},
+    profile::Machine,
+    template::Vars,
+};
+
+#[derive(Debug, Args)]
+pub struct StatusArg {
+    /// The package(s) whose destinations will be checked
+    #[arg(
+        value_name = "packages",
+        group = "specify",
+        help = "The package(s) whose destinations will be checked"
+    )]
+    pub pkgs: Vec<String>,
+
+    /// Check all packages, the default without a profile on this machine
+    #[arg(
+        short,
+        long,
+        group = "specify",
+        help = "Check all packages, the default without a profile on this machine"
+    )]
+    pub all: bool,
+
+    /// Check the packages of a profile, defaults to the profile of this machine
+    #[arg(
+        short,
+        long,
+        group = "specify",
+        help = "Check the packages of a profile, defaults to the profile of this machine"
+    )]
+    pub profile: Option<String>,
+
+    /// Also list destinations that are in place
+    #[arg(short, long, help = "Also list destinations that are in place")]
+    pub verbose: bool,
+
+    /// Exit non-zero if any destination drifted
+    #[arg(long, help = "Exit non-zero if any destination drifted")]
+    pub check: bool,
+}
+
+// LYN: Main
+
+pub fn main(arg: &StatusArg) -> eyre::Result<ExitCode> {
+    let all = arg.all
+        || arg.pkgs.is_empty() && arg.profile.is_none() && Machine::load()?.profile.is_none();
+    let mut selection = pkg::select(&arg.pkgs, all, arg.profile.as_deref(), false)?;
+    let vars = Vars::load()?;
+    let ledger = LinkLedger::load()?;
+
+    let mut summary = StatusSummary {
+        profile: selection.profile.clone(),
+        non_exist: mem::take(&mut selection.non_exist),
+        dep_issues: mem::take(&mut selection.dep_issues),
+        ..Default::default()
+    };
+    let mut plans = Vec::new();
+    for pkg_name in &selection.ordered {
+        let manifest = selection.manifest(pkg_name)?;
+        plans.extend(PkgPlan::prob(pkg_name, &manifest, &vars, &ledger)?);
+    }
+    settle_collisions(&mut plans);
+
+    let mut plans = plans.into_iter().peekable();
+    for pkg_name in &selection.ordered {
+        let dests = match plans.next_if(|plan| plan.pkg_name == *pkg_name) {
+            Some(plan) => plan.detail_pack.iter().filter_map(DestStatus::of).collect(),
+            None => Vec::new(),
+        };
+        summary.statuses.push(PkgStatus {
+            pkg_name: pkg_name.to_owned(),
+            dests,
+        });
+    }
+
+    summary.display(arg.verbose);
+
+    let mut failures = summary.failures();
+    if !arg.check {
+        failures.retain(|failure| *failure != Failure::Drifted);
+    }
+    Ok(exit::code_of(failures))
+}
+
+// LYN: Status Summary
+
+#[derive(Debug, Default)]
+struct StatusSummary {
+    /// The profile the packages were selected by
+    profile: Option<String>,
+    /// Packages and the state of their destinations
+    statuses: Vec<PkgStatus>,
+    /// package names that do not exist
+    non_exist: Vec<String>,
+    /// Packages skipped because of their dependencies
+    dep_issues: Vec<DepIssue>,
+}
+
+impl StatusSummary {
+    /// What went wrong, deciding the exit code
+    fn failures(&self) -> Vec<Failure> {
+        let mut failures = Vec::new();
+        if !self.non_exist.is_empty() || !self.dep_issues.is_empty() {
+            failures.push(Failure::PackageMissing);
+        }
+        if self
+            .statuses
+            .iter()
+            .flat_map(|status| &status.dests)
+            .any(|dest| dest.state != DestState::Linked)
+        {
+            failures.push(Failure::Drifted);
+        }
+        failures
+    }
+
+    fn display(&self, verbose: bool) {
+        println!("{}", "Status:".bold().bright_green());
+        if let Some(profile) = &self.profile {
+            println!("- Using profile {}", format!("`{}`", profile).yellow());
+        }
+
+        let width = DestState::ALL
+            .iter()
+            .map(|state| state.to_string().len())
+            .max()
+            .unwrap_or_default();
+        for status in &self.statuses {
+            let drifted = status
+                .dests
+                .iter()
+                .filter(|dest| dest.state != DestState::Linked)
+                .count();
+            println!(
+                "- Package {} {}",
+                format!("`{}`", status.pkg_name).yellow(),
+                if status.dests.is_empty() {
+                    "has nothing to link".bright_black().to_string()
+                } else if drifted == 0 {
+                    format!("all {} in place", status.dests.len())
+                        .bright_green()
+                        .to_string()
+                } else {
+                    format!("{drifted} of {} drifted", status.dests.len())
+                        .bright_red()
+                        .to_string()
+                }
+            );
+            for dest in &status.dests {
+                if dest.state == DestState::Linked && !verbose {
+                    continue;
+                }
+                let state = format!("{:width$}", dest.state.to_string());
+                println!(
+                    "  {} {} {}",
+                    match dest.state {
+                        DestState::Linked => state.bright_green().to_string(),
+                        DestState::Missing => state.yellow().to_string(),
+                        _ => state.bright_red().to_string(),
+                    },
+                    format!("`{}`", dest.dest_path.display()).cyan(),
+                    dest.note.bright_black(),
+                );
+            }
+        }
+        for pkg_name in &self.non_exist {
+            println!(
+                "- Package {} doesn't exist",
+                format!("`{}`", pkg_name).yellow()
+            );
+        }
+        for issue in &self.dep_issues {
+            println!("- {}", issue);
+        }
+    }
+}
+
+#[derive(Debug)]
+struct PkgStatus {
+    pkg_name: String,
+    /// Every destination the package makes
+    dests: Vec<DestStatus>,
+}
+
+#[derive(Debug)]
+struct DestStatus {
+    dest_path: PathBuf,
+    state: DestState,
+    /// What exactly is there, if not in place
+    note: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DestState {
+    /// The destination is what linking would make
+    Linked,
+    /// Nothing is at the destination
+    Missing,
+    /// A regular file or directory is at the destination
+    Occupied,
+    /// A symlink to somewhere else is at the destination
+    Elsewhere,
+    /// A symlink to nothing is at the destination
+    Dangling,
+    /// A copied or rendered destination differs from what linking would write
+    Modified,
+    /// Other packages claim the destination too, without settling who links it
+    Conflict,
+    /// The source cannot be read or rendered
+    Broken,
+}
+
+impl DestState {
+    const ALL: [DestState; 8] = [
+        DestState::Linked,
+        DestState::Missing,
+        DestState::Occupied,
+        DestState::Elsewhere,
+        DestState::Dangling,
+        DestState::Modified,
+        DestState::Conflict,
+        DestState::Broken,
+    ];
+}
+
+impl Display for DestState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            DestState::Linked => write!(f, "linked"),
+            DestState::Missing => write!(f, "missing"),
+            DestState::Occupied => write!(f, "occupied"),
+            DestState::Elsewhere => write!(f, "elsewhere"),
+            DestState::Dangling => write!(f, "dangling"),
+            DestState::Modified => write!(f, "modified"),
+            DestState::Conflict => write!(f, "conflict"),
+            DestState::Broken => write!(f, "broken"),
+        }
+    }
+}
+
+impl DestStatus {
+    /// Classify the destination of the pack, `None` if it is not about a destination
+    fn of(pack: &LinkDetailPack) -> Option<Self> {
+        let (dest_path, state, note) = match pack {
+            LinkDetailPack::AlreadyLinked { dest_path, .. }
+            | LinkDetailPack::AlreadyCopied { dest_path, .. } => {
+                (dest_path, DestState::Linked, String::new())
+            }
+            LinkDetailPack::Linkable { dest_path, .. } => {
+                (dest_path, DestState::Missing, String::new())
+            }
+            LinkDetailPack::Copyable {
+                dest_path,
+                copied: Some(Err(e)),
+                ..
+            } => (dest_path, DestState::Broken, e.to_string()),
+            LinkDetailPack::Copyable { dest_path, .. } if dest_path.is_symlink() => (
+                dest_path,
+                DestState::Modified,
+                "symlink where a copy belongs".to_owned(),
+            ),
+            LinkDetailPack::Copyable { dest_path, .. } => {
+                (dest_path, DestState::Missing, String::new())
+            }
+            LinkDetailPack::DestOccupied {
+                dest_path,
+                occupant,
+                ..
+            } => {
+                let state = match occupant {
+                    Occupant::LinkedToOtherPackage { .. } | Occupant::ForeignSymlink { .. } => {
+                        DestState::Elsewhere
+                    }
+                    Occupant::DanglingSymlink { .. } => DestState::Dangling,
+                    Occupant::OccupiedByFile | Occupant::OccupiedByDir => DestState::Occupied,
+                };
+                (dest_path, state, occupant.to_string())
+            }
+            LinkDetailPack::CopySourceChanged {
+                dest_path, mode, ..
+            } => (
+                dest_path,
+                DestState::Modified,
+                match mode {
+                    LinkMode::Template => "output differs from disk",
+                    _ => "source changed since copied",
+                }
+                .to_owned(),
+            ),
+            LinkDetailPack::CopyDestEdited { dest_path, .. } => {
+                (dest_path, DestState::Modified, "edited locally".to_owned())
+            }
+            LinkDetailPack::Collision {
+                dest_path, with, ..
+            } => (
+                dest_path,
+                DestState::Conflict,
+                format!(
+                    "also claimed by {}",
+                    with.iter()
+                        .map(|pkg_name| format!("package `{pkg_name}`"))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            ),
+            LinkDetailPack::Ignored { .. }
+            | LinkDetailPack::VariantChosen { .. }
+            | LinkDetailPack::NoVariant { .. }
+            | LinkDetailPack::Overridden { .. } => return None,
+        };
+        Some(Self {
+            dest_path: dest_path.to_owned(),
+            state,
+            note,
+        })
+    }
+}
@@ -0,0 +1,417 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use clap::Args;
+use log::{trace, warn};
+use owo_colors::OwoColorize;
+
+use crate::{
+    backup::{BackupIndex, legacy_backup_of, move_path},
+    cli::link::dest_for,
+    dir::{Dir, exists, get},
+    ledger::{LinkLedger, LinkMode},
+    manifest::Manifest,
+    pkg,
+    variant::is_variant,
+};
+
+#[derive(Debug, Args)]
+pub struct UnlinkArg {
+    /// The package(s) whose files will be unlinked
+    #[arg(
+        value_name = "packages",
+        group = "specify",
+        required = true,
+        help = "The package(s) whose files will be unlinked"
+    )]
+    pub pkgs: Vec<String>,
+
+    /// Unlink files for all packages
+    #[arg(short, long, group = "specify", help = "Unlink files for all packages")]
+    pub all: bool,
+
+    /// Dry run mode, only prints what links would be removed
+    #[arg(
+        short,
+        long,
+        help = "Dry run mode, only print what links would be removed"
+    )]
+    pub dry: bool,
+}
+
+// LYN: Main
+
+pub fn main(arg: &UnlinkArg) -> eyre::Result<()> {
+    let mut ledger = LinkLedger::load()?;
+    let mut backups = BackupIndex::load()?;
+    let pkg_names = if arg.all {
+        pkg::all()?
+    } else {
+        arg.pkgs.clone()
+    };
+    let mut summary = UnlinkSummary::default();
+    for pkg_name in &pkg_names {
+        unlink_pkg(pkg_name, arg, &mut summary, &mut ledger, &mut backups)?;
+    }
+    if !arg.dry {
+        ledger.save()?;
+        backups.save()?;
+    }
+
+    summary.display();
+
+    Ok(())
+}
+
+// LYN: Unlink Summary
+
+#[derive(Debug, Default)]
+struct UnlinkSummary {
+    /// Packages whose files were unlinked and the unlinking detail
+    details: Vec<UnlinkDetail>,
+    /// package names that do not exist
+    non_exist: Vec<String>,
+    /// package names that do not have files
+    no_files: Vec<String>,
+}
+
+impl UnlinkSummary {
+    fn display(&self) {
+        println!("{}", "Unlink Summary:".bold().bright_green());
+
+        for detail in &self.details {
+            println!(
+                "- Package {} processed {} file(s) {}",
+                format!("`{}`", detail.pkg_name).yellow(),
+                detail.detail_pack.len(),
+                format!("in `{}`", detail.target.display()).bright_black(),
+            );
+            for pack in &detail.detail_pack {
+                match pack {
+                    UnlinkDetailPack::Unlinkable {
+                        src_path,
+                        dest_path,
+                        backup_path,
+                        unlinked,
+                    } => {
+                        let restore = backup_path
+                            .as_ref()
+                            .map(|path| format!(", restores `{}`", path.display()))
+                            .unwrap_or_default();
+                        if let Some(unlinked) = unlinked {
+                            if let Err(e) = unlinked {
+                                println!(
+                                    "  - {} {} due to {e}",
+                                    "Failed".bold().on_red(),
+                                    format!("`{}`", dest_path.display()).cyan(),
+                                );
+                            } else {
+                                println!(
+                                    "  - {} {} {}",
+                                    "Unlinked".bright_green(),
+                                    format!("`{}`", dest_path.display()).cyan(),
+                                    format!("-> `{}`{}", src_path.display(), restore)
+                                        .bright_black(),
+                                );
+                            }
+                        } else {
+                            println!(
+                                "  - {} {} {}",
+                                "Planed".bright_green(),
+                                format!("`{}`", dest_path.display()).cyan(),
+                                format!("-> `{}`{}", src_path.display(), restore).bright_black(),
+                            );
+                        }
+                    }
+                    UnlinkDetailPack::NotLinked {
+                        src_path,
+                        dest_path,
+                    } => {
+                        println!(
+                            "  - {} {} {}",
+                            "Skipped".bright_black(),
+                            format!("`{}`", dest_path.display()).cyan(),
+                            format!("is not linked to `{}`", src_path.display()).bright_black(),
+                        );
+                    }
+                }
+            }
+        }
+        for pkg_name in &self.non_exist {
+            println!(
+                "- Package {} doesn't exist",
+                format!("`{}`", pkg_name).yellow()
+            );
+        }
+        for pkg_name in &self.no_files {
+            println!(
+                "- Package {} has no files to unlink",
+                format!("`{}`", pkg_name).yellow()
+            );
+        }
+    }
+}
+
+#[derive(Debug)]
+struct UnlinkDetail {
+    /// The name of the package whose files were unlinked
+    pkg_name: String,
+    /// The directory the package files are linked into
+    target: PathBuf,
+    /// The detail of the files unlinked for the package
+    detail_pack: Vec<UnlinkDetailPack>,
+}
+
+#[derive(Debug)]
+enum UnlinkDetailPack {
+    Unlinkable {
+        src_path: PathBuf,
+        dest_path: PathBuf,
+        backup_path: Option<PathBuf>,
+        unlinked: Option<io::Result<()>>,
+    },
+    NotLinked {
+        src_path: PathBuf,
+        dest_path: PathBuf,
+    },
+}
+
+// LYN: Unlink Files
+
+/// Unlink files for the package, noting it in the summary when it has nothing to unlink
+fn unlink_pkg(
+    pkg_name: &str,
+    arg: &UnlinkArg,
+    summary: &mut UnlinkSummary,
+    ledger: &mut LinkLedger,
+    backups: &mut BackupIndex,
+) -> eyre::Result<()> {
+    if !exists(Dir::Pkg {
+        pkg_name: pkg_name.to_owned(),
+    })? {
+        summary.non_exist.push(pkg_name.to_owned());
+        warn!("Package `{}` does not exist", pkg_name);
+        return Ok(());
+    }
+    if !exists(Dir::Files {
+        pkg_name: pkg_name.to_owned(),
+    })? {
+        summary.no_files.push(pkg_name.to_owned());
+        warn!("Package `{}` does not have a files folder", pkg_name);
+        return Ok(());
+    }
+
+    let target = Manifest::load(pkg_name)?.target_dir()?;
+    let mut detail_pack = prob_unlink(
+        &get(Dir::Files {
+            pkg_name: pkg_name.to_owned(),
+        }),
+        &target,
+        ledger,
+        backups,
+    )?;
+    detail_pack.append(&mut prob_stale(pkg_name, &detail_pack, ledger));
+    if !arg.dry {
+        make_unlink(&mut detail_pack, ledger, backups);
+    }
+    summary.details.push(UnlinkDetail {
+        pkg_name: pkg_name.to_owned(),
+        target,
+        detail_pack,
+    });
+    Ok(())
+}
+
+/// Find the links in the target path that point back to the files in the given path
+fn prob_unlink(
+    path: &Path,
+    target: &Path,
+    ledger: &LinkLedger,
+    backups: &BackupIndex,
+) -> eyre::Result<Vec<UnlinkDetailPack>> {
+    let mut detail_pack = Vec::new();
+    for file_entry in path.read_dir()? {
+        let file_entry = file_entry?;
+        let src = file_entry.path();
+        let dest = dest_for(&src, target)?;
+
+        if dest.is_symlink() && dest.read_link().is_ok_and(|path| path == src) {
+            // Destination is symlink and points to src, ours to remove
+            let backup_path = match ledger.find(&dest) {
+                Some(record) => record.backup_path.clone(),
+                None => match backups.latest_for(&dest) {
+                    Some(record) => Some(record.backup_path.clone()),
+                    None => legacy_backup_of(&dest)?,
+                },
+            };
+            detail_pack.push(UnlinkDetailPack::Unlinkable {
+                backup_path,
+                src_path: src,
+                dest_path: dest,
+                unlinked: None,
+            });
+        } else if let Some(record) = ledger.find(&dest).filter(|record| {
+            record.mode != LinkMode::Symlink && record.src_path == src && record.is_intact()
+        }) {
+            // Destination is an untouched copy or render of src, ours to remove
+            detail_pack.push(UnlinkDetailPack::Unlinkable {
+                backup_path: record.backup_path.clone(),
+                src_path: src,
+                dest_path: dest,
+                unlinked: None,
+            });
+        } else if !dest.is_symlink() && dest.is_dir() && src.is_dir() {
+            // Destination and Source both dir, recursive unlink
+            detail_pack.append(&mut prob_unlink(&src, &dest, ledger, backups)?);
+        } else if is_variant(&src) {
+            // A variant not picked for this machine, nothing to report
+            trace!("Skipping variant {:?} not linked to {:?}", src, dest);
+        } else {
+            // Destination missing or not linked by us, leave it alone
+            detail_pack.push(UnlinkDetailPack::NotLinked {
+                src_path: src,
+                dest_path: dest,
+            });
+        }
+    }
+
+    Ok(detail_pack)
+}
+
+/// Find the recorded links of a package whose source is no longer in the package
+fn prob_stale(
+    pkg_name: &str,
+    probed: &[UnlinkDetailPack],
+    ledger: &LinkLedger,
+) -> Vec<UnlinkDetailPack> {
+    let probed_dest = |dest: &Path| {
+        probed.iter().any(|pack| match pack {
+            UnlinkDetailPack::Unlinkable { dest_path, .. }
+            | UnlinkDetailPack::NotLinked { dest_path, .. } => dest_path == dest,
+        })
+    };
+
+    ledger
+        .records()
+        .iter()
+        .filter(|record| record.pkg_name == pkg_name && !probed_dest(&record.dest_path))
+        .map(|record| {
+            if record.is_intact() {
+                UnlinkDetailPack::Unlinkable {
+                    src_path: record.src_path.clone(),
+                    dest_path: record.dest_path.clone(),
+                    backup_path: record.backup_path.clone(),
+                    unlinked: None,
+                }
+            } else {
+                UnlinkDetailPack::NotLinked {
+                    src_path: record.src_path.clone(),
+                    dest_path: record.dest_path.clone(),
+                }
+            }
+        })
+        .collect()
+}
+
+/// Remove the link and put its backup back, if any.
+///
+/// The backup is first moved next to the destination and only forgotten once in place, so a
+/// backup that cannot be restored leaves the link, its record and the backup as they were.
+fn unlink_restoring(
+    dest_path: &Path,
+    backup_path: Option<&Path>,
+    ledger: &mut LinkLedger,
+    backups: &mut BackupIndex,
+) -> io::Result<()> {
+    let Some(backup_path) = backup_path else {
+        fs::remove_file(dest_path)?;
+        ledger.remove(dest_path);
+        return Ok(());
+    };
+
+    trace!("Restoring backup {:?}", backup_path);
+    let staged = dest_path.with_file_name(format!(
+        ".{}.dottie-restore",
+        dest_path.file_name().unwrap_or_default().to_string_lossy()
+    ));
+    move_path(backup_path, &staged)?;
+    if let Err(e) = fs::remove_file(dest_path).and_then(|_| fs::rename(&staged, dest_path)) {
+        // Back where the records point, for a retry or `dottie backup restore`
+        if let Err(e) = move_path(&staged, backup_path) {
+            warn!("Backup {:?} left at {:?}: {}", backup_path, staged, e);
+        }
+        return Err(e);
+    }
+    backups.forget(backup_path);
+    ledger.remove(dest_path);
+    Ok(())
+}
+
+/// Remove the links and restore their backups, if any, keeping the ledger in sync
+fn make_unlink(
+    packs: &mut Vec<UnlinkDetailPack>,
+    ledger: &mut LinkLedger,
+    backups: &mut BackupIndex,
+) {
+    for pack in packs {
+        match pack {
+            UnlinkDetailPack::Unlinkable {
+                dest_path,
+                backup_path,
+                unlinked,
+                ..
+            } => {
+                trace!("Unlinking {:?}", dest_path);
+                *unlinked = Some(unlink_restoring(
+                    dest_path,
+                    backup_path.as_deref(),
+                    ledger,
+                    backups,
+                ));
+            }
+            UnlinkDetailPack::NotLinked {
+                src_path,
+                dest_path,
+            } => {
+                trace!("Ignoring not linked {:?} -> {:?}", dest_path, src_path);
+                if ledger
+                    .find(dest_path)
+                    .is_some_and(|record| record.src_path == *src_path)
+                {
+                    trace!("Forgetting stale link record {:?}", dest_path);
+                    ledger.remove(dest_path);
+                }
+                continue;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failed_unlink_keeps_backup_where_recorded() {
+        let dir = std::env::temp_dir().join(format!("dottie-{}-unlink", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let backup_path = dir.join("backup");
+        fs::write(&backup_path, "backup").unwrap();
+        // A directory cannot be removed as a link
+        let dest_path = dir.join("dest");
+        fs::create_dir(&dest_path).unwrap();
+
+        let mut ledger = LinkLedger::default();
+        let mut backups = BackupIndex::default();
+        let unlinked = unlink_restoring(&dest_path, Some(&backup_path), &mut ledger, &mut backups);
+
+        assert!(unlinked.is_err());
+        assert_eq!(fs::read_to_string(&backup_path).unwrap(), "backup");
+        assert!(!dir.join(".dest.dottie-restore").exists());
+        assert!(dest_path.is_dir());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
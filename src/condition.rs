@@ -0,0 +1,240 @@
+use std::{
+    env,
+    fmt::{self, Display, Formatter},
+    os::unix::fs::PermissionsExt,
+};
+
+use globset::Glob;
+use thiserror::Error;
+
+use crate::host::Host;
+
+#[derive(Debug, Error)]
+pub enum ConditionError {
+    #[error(
+        "Unknown condition `{0}`, expected `default`, `host.`, `user.`, `os.`, `distro.`, `arch.` or `has.`"
+    )]
+    Unknown(String),
+    #[error("Invalid hostname pattern `{0}`: {1}")]
+    InvalidPattern(String, globset::Error),
+}
+
+/// A fact about the machine that must hold, e.g. `os.linux` or `has.systemctl`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Condition {
+    /// Always holds
+    Default,
+    /// The hostname matches the glob pattern
+    Host(String),
+    User(String),
+    Os(String),
+    Distro(String),
+    Arch(String),
+    /// The command is an executable in `PATH`
+    Has(String),
+}
+
+impl Display for Condition {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Condition::Default => write!(f, "default"),
+            Condition::Host(pattern) => write!(f, "host `{pattern}`"),
+            Condition::User(user) => write!(f, "user `{user}`"),
+            Condition::Os(os) => write!(f, "os `{os}`"),
+            Condition::Distro(distro) => write!(f, "distro `{distro}`"),
+            Condition::Arch(arch) => write!(f, "arch `{arch}`"),
+            Condition::Has(command) => write!(f, "command `{command}`"),
+        }
+    }
+}
+
+impl Condition {
+    /// Parse a single `key.value` condition
+    pub fn parse(raw: &str) -> Result<Self, ConditionError> {
+        if raw == "default" {
+            return Ok(Self::Default);
+        }
+        let unknown = || ConditionError::Unknown(raw.to_owned());
+        let (key, value) = raw.split_once('.').ok_or_else(unknown)?;
+        if value.is_empty() {
+            return Err(unknown());
+        }
+        let value = value.to_owned();
+        Ok(match key {
+            "host" | "hostname" => {
+                Glob::new(&value).map_err(|e| ConditionError::InvalidPattern(value.clone(), e))?;
+                Self::Host(value)
+            }
+            "user" => Self::User(value),
+            "os" => Self::Os(value),
+            "distro" => Self::Distro(value),
+            "arch" => Self::Arch(value),
+            "has" => Self::Has(value),
+            _ => return Err(unknown()),
+        })
+    }
+
+    /// Parse comma separated conditions, e.g. `os.linux,has.systemctl`
+    pub fn parse_list(raw: &str) -> Result<Vec<Self>, ConditionError> {
+        raw.split(',').map(Self::parse).collect()
+    }
+
+    pub fn holds(&self, host: &Host) -> bool {
+        match self {
+            Condition::Default => true,
+            Condition::Host(pattern) => {
+                Glob::new(pattern).is_ok_and(|glob| glob.compile_matcher().is_match(&host.hostname))
+            }
+            Condition::User(user) => *user == host.user,
+            Condition::Os(os) => *os == host.os,
+            Condition::Distro(distro) => *distro == host.distro,
+            Condition::Arch(arch) => *arch == host.arch,
+            Condition::Has(command) => in_path(command),
+        }
+    }
+}
+
+/// How specific a set of conditions is, compared key by key from `host` down to `arch` and
+/// `has`, so one condition on a more specific key outweighs any number on less specific ones
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Specificity([usize; 5]);
+
+impl Specificity {
+    pub fn of(conditions: &[Condition]) -> Self {
+        let mut counts = [0; 5];
+        for condition in conditions {
+            let key = match condition {
+                Condition::Default => continue,
+                Condition::Host(_) => 0,
+                Condition::User(_) => 1,
+                Condition::Distro(_) => 2,
+                Condition::Os(_) => 3,
+                Condition::Arch(_) | Condition::Has(_) => 4,
+            };
+            counts[key] += 1;
+        }
+        Self(counts)
+    }
+}
+
+/// The first of the conditions that does not hold, if any
+pub fn first_unmet<'a>(conditions: &'a [Condition], host: &Host) -> Option<&'a Condition> {
+    conditions.iter().find(|condition| !condition.holds(host))
+}
+
+/// Whether an executable of the given name is in `PATH`
+fn in_path(command: &str) -> bool {
+    let Some(paths) = env::var_os("PATH") else {
+        return false;
+    };
+    env::split_paths(&paths).any(|dir| {
+        dir.join(command)
+            .metadata()
+            .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host() -> Host {
+        Host {
+            hostname: "laptop-01".to_owned(),
+            os: "linux".to_owned(),
+            distro: "arch".to_owned(),
+            arch: "x86_64".to_owned(),
+            user: "me".to_owned(),
+        }
+    }
+
+    #[test]
+    fn parse_known_keys() {
+        assert_eq!(Condition::parse("default").unwrap(), Condition::Default);
+        assert_eq!(
+            Condition::parse("host.laptop-*").unwrap(),
+            Condition::Host("laptop-*".to_owned())
+        );
+        assert_eq!(
+            Condition::parse("hostname.pc").unwrap(),
+            Condition::Host("pc".to_owned())
+        );
+        assert_eq!(
+            Condition::parse("distro.arch").unwrap(),
+            Condition::Distro("arch".to_owned())
+        );
+        assert_eq!(
+            Condition::parse("has.git.exe").unwrap(),
+            Condition::Has("git.exe".to_owned())
+        );
+    }
+
+    #[test]
+    fn parse_rejects_unknown() {
+        for raw in ["", "linux", "os.", "shell.zsh", "Default"] {
+            assert!(
+                matches!(Condition::parse(raw), Err(ConditionError::Unknown(_))),
+                "{raw:?} should be unknown"
+            );
+        }
+        assert!(matches!(
+            Condition::parse("host.[laptop"),
+            Err(ConditionError::InvalidPattern(..))
+        ));
+    }
+
+    #[test]
+    fn parse_list_fails_on_any_bad_entry() {
+        assert_eq!(
+            Condition::parse_list("os.linux,user.me").unwrap(),
+            [
+                Condition::Os("linux".to_owned()),
+                Condition::User("me".to_owned())
+            ]
+        );
+        assert!(Condition::parse_list("os.linux,").is_err());
+    }
+
+    fn specificity(raw: &str) -> Specificity {
+        Specificity::of(&Condition::parse_list(raw).unwrap())
+    }
+
+    #[test]
+    fn specificity_ranks_host_user_distro_os_arch() {
+        let ranked = ["host.h", "user.u", "distro.d", "os.o", "arch.a", "default"].map(specificity);
+        assert!(
+            ranked.windows(2).all(|pair| pair[0] > pair[1]),
+            "{ranked:?}"
+        );
+        assert_eq!(specificity("has.git"), specificity("arch.a"));
+        assert_eq!(specificity("os.o,default"), specificity("os.o"));
+    }
+
+    #[test]
+    fn specificity_key_outweighs_all_less_specific_ones() {
+        // Each key beats every combination of the less specific keys
+        let keys = ["host.h", "user.u", "distro.d", "os.o", "arch.a,has.a,has.b"];
+        for (i, key) in keys.iter().enumerate() {
+            let rest = keys[i + 1..].join(",");
+            if !rest.is_empty() {
+                assert!(specificity(key) > specificity(&rest), "{key} vs {rest}");
+            }
+        }
+        assert!(specificity("os.o,has.a") > specificity("os.o"));
+    }
+
+    #[test]
+    fn holds_against_host() {
+        let host = host();
+        let holds = |raw: &str| Condition::parse(raw).unwrap().holds(&host);
+        assert!(holds("host.laptop-*"));
+        assert!(!holds("host.desktop"));
+        assert!(holds("distro.arch"));
+        assert!(!holds("arch.aarch64"));
+        assert!(!holds("has.dottie-surely-missing-command"));
+        assert_eq!(
+            first_unmet(&Condition::parse_list("os.linux,user.you").unwrap(), &host),
+            Some(&Condition::User("you".to_owned()))
+        );
+    }
+}
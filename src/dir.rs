@@ -16,21 +16,52 @@ pub static APP_DIR: LazyLock<PathBuf> = LazyLock::new(|| {
         .expect("Home directory is not available")
         .join(".dottie")
 });
+/// Where the state of this machine is kept, outside of `~/.dottie` so it is never committed
+pub static STATE_DIR: LazyLock<PathBuf> = LazyLock::new(|| {
+    env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .unwrap_or_else(|| HOME_DIR.join(".local/state"))
+        .join("dottie")
+});
 pub static FILES_POSTFIX: &str = "files";
 pub static SCRIPTS_POSTFIX: &str = "scripts";
+pub static PROFILES_POSTFIX: &str = ".profiles";
+pub static TEMPLATES_POSTFIX: &str = ".templates";
+pub static BACKUPS_POSTFIX: &str = "backups";
 
 #[derive(Debug, Clone)]
 pub enum Dir {
     App,
-    Pkg { pkg_name: String },
-    Files { pkg_name: String },
-    Scripts { pkg_name: String },
+    State,
+    Profiles,
+    /// Package templates `dottie new` starts from
+    Templates,
+    /// Destinations `link --force` moved out of the way
+    Backups,
+    Pkg {
+        pkg_name: String,
+    },
+    Files {
+        pkg_name: String,
+    },
+    Scripts {
+        pkg_name: String,
+    },
 }
 
 impl Display for Dir {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
             Dir::App => write!(f, "App directory `~/.dottie`"),
+            Dir::State => write!(f, "State directory `{}`", STATE_DIR.display()),
+            Dir::Profiles => write!(f, "Profiles directory `~/.dottie/.profiles`"),
+            Dir::Templates => write!(f, "Templates directory `~/.dottie/.templates`"),
+            Dir::Backups => write!(
+                f,
+                "Backups directory `{}`",
+                STATE_DIR.join(BACKUPS_POSTFIX).display()
+            ),
             Dir::Pkg { pkg_name } => write!(f, "Package Directory: {}", pkg_name),
             Dir::Files { pkg_name } => write!(f, "Files Directory for Package: {}", pkg_name),
             Dir::Scripts { pkg_name } => write!(f, "Scripts Directory for Package: {}", pkg_name),
@@ -40,6 +71,10 @@ impl Display for Dir {
 pub fn get(dir: Dir) -> PathBuf {
     match dir {
         Dir::App => APP_DIR.clone(),
+        Dir::State => STATE_DIR.clone(),
+        Dir::Profiles => APP_DIR.join(PROFILES_POSTFIX),
+        Dir::Templates => APP_DIR.join(TEMPLATES_POSTFIX),
+        Dir::Backups => STATE_DIR.join(BACKUPS_POSTFIX),
         Dir::Pkg { pkg_name } => APP_DIR.join(pkg_name),
         Dir::Files { pkg_name } => APP_DIR.join(pkg_name).join(FILES_POSTFIX),
         Dir::Scripts { pkg_name } => APP_DIR.join(pkg_name).join(SCRIPTS_POSTFIX),
@@ -52,12 +87,22 @@ pub enum DirError {
     CannotConfirmDirExistence(Dir, io::Error),
     #[error("Failed to create directory {0}: {1}")]
     FailedToCreateDir(Dir, io::Error),
+    #[error("Environment variable `{1}` used in `{0}` is not set")]
+    UnsetEnvVar(String, String),
 }
 
 pub fn exists(dir: Dir) -> eyre::Result<bool> {
     Ok(match dir {
         Dir::App => fs::exists(APP_DIR.as_path())
             .map_err(|e| DirError::CannotConfirmDirExistence(dir, e))?,
+        Dir::State => fs::exists(STATE_DIR.as_path())
+            .map_err(|e| DirError::CannotConfirmDirExistence(dir, e))?,
+        Dir::Profiles => fs::exists(APP_DIR.join(PROFILES_POSTFIX))
+            .map_err(|e| DirError::CannotConfirmDirExistence(dir, e))?,
+        Dir::Templates => fs::exists(APP_DIR.join(TEMPLATES_POSTFIX))
+            .map_err(|e| DirError::CannotConfirmDirExistence(dir, e))?,
+        Dir::Backups => fs::exists(STATE_DIR.join(BACKUPS_POSTFIX))
+            .map_err(|e| DirError::CannotConfirmDirExistence(dir, e))?,
         Dir::Pkg { ref pkg_name } => fs::exists(APP_DIR.join(pkg_name))
             .map_err(|e| DirError::CannotConfirmDirExistence(dir, e))?,
         Dir::Files { ref pkg_name } => fs::exists(APP_DIR.join(pkg_name).join(FILES_POSTFIX))
@@ -80,6 +125,45 @@ pub fn ensure_exists(dir: Dir) -> eyre::Result<()> {
                     .map_err(|e| DirError::FailedToCreateDir(dir.clone(), e))?;
             }
         }
+        Dir::State => {
+            if !fs::exists(STATE_DIR.as_path())
+                .map_err(|e| DirError::CannotConfirmDirExistence(dir.clone(), e))?
+            {
+                warn!("State directory doesn't exist, creating...");
+                fs::create_dir_all(STATE_DIR.as_path())
+                    .map_err(|e| DirError::FailedToCreateDir(dir.clone(), e))?;
+            }
+        }
+        Dir::Profiles => {
+            let profiles_dir = APP_DIR.join(PROFILES_POSTFIX);
+            if !fs::exists(&profiles_dir)
+                .map_err(|e| DirError::CannotConfirmDirExistence(dir.clone(), e))?
+            {
+                warn!("Profiles directory doesn't exist, creating...");
+                fs::create_dir(&profiles_dir)
+                    .map_err(|e| DirError::FailedToCreateDir(dir.clone(), e))?;
+            }
+        }
+        Dir::Templates => {
+            let templates_dir = APP_DIR.join(TEMPLATES_POSTFIX);
+            if !fs::exists(&templates_dir)
+                .map_err(|e| DirError::CannotConfirmDirExistence(dir.clone(), e))?
+            {
+                warn!("Templates directory doesn't exist, creating...");
+                fs::create_dir(&templates_dir)
+                    .map_err(|e| DirError::FailedToCreateDir(dir.clone(), e))?;
+            }
+        }
+        Dir::Backups => {
+            let backups_dir = STATE_DIR.join(BACKUPS_POSTFIX);
+            if !fs::exists(&backups_dir)
+                .map_err(|e| DirError::CannotConfirmDirExistence(dir.clone(), e))?
+            {
+                warn!("Backups directory doesn't exist, creating...");
+                fs::create_dir_all(&backups_dir)
+                    .map_err(|e| DirError::FailedToCreateDir(dir.clone(), e))?;
+            }
+        }
         Dir::Pkg { ref pkg_name } => {
             let pkg_dir = APP_DIR.join(pkg_name);
             if !fs::exists(&pkg_dir)
@@ -122,13 +206,121 @@ pub fn ensure_exists(dir: Dir) -> eyre::Result<()> {
 }
 
 pub fn not_package(path: &Path) -> bool {
-    if let Some(file_name) = path.file_name() {
-        if file_name.to_string_lossy().starts_with('.') {
-            return true;
-        }
+    if let Some(file_name) = path.file_name()
+        && file_name.to_string_lossy().starts_with('.')
+    {
+        return true;
     }
     if path.is_file() {
         return true;
     }
     false
 }
+
+/// Whether the name can be used for a package, mirroring what `not_package` skips
+pub fn is_valid_pkg_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && !name.contains(std::path::MAIN_SEPARATOR)
+        && !name.contains('/')
+}
+
+/// Expand a user supplied path: `~` and `$VAR`/`${VAR}` are substituted, XDG base directories
+/// fall back to their defaults, and relative paths are taken relative to the home directory.
+pub fn expand_path(raw: &str) -> Result<PathBuf, DirError> {
+    let mut expanded = String::with_capacity(raw.len());
+    let mut rest = raw;
+    if let Some(stripped) = rest.strip_prefix('~')
+        && (stripped.is_empty() || stripped.starts_with('/'))
+    {
+        expanded.push_str(&HOME_DIR.to_string_lossy());
+        rest = stripped;
+    }
+
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let (name, next) = if let Some(braced) = after.strip_prefix('{') {
+            match braced.find('}') {
+                Some(end) => (&braced[..end], &braced[end + 1..]),
+                None => (braced, ""),
+            }
+        } else {
+            let end = after
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(after.len());
+            (&after[..end], &after[end..])
+        };
+        if name.is_empty() {
+            expanded.push('$');
+        } else {
+            let value = env::var(name)
+                .ok()
+                .filter(|value| !value.is_empty())
+                .or_else(|| xdg_default(name))
+                .ok_or_else(|| DirError::UnsetEnvVar(raw.to_owned(), name.to_owned()))?;
+            expanded.push_str(&value);
+        }
+        rest = next;
+    }
+    expanded.push_str(rest);
+
+    Ok(HOME_DIR.join(expanded))
+}
+
+fn xdg_default(name: &str) -> Option<String> {
+    let relative = match name {
+        "XDG_CONFIG_HOME" => ".config",
+        "XDG_DATA_HOME" => ".local/share",
+        "XDG_STATE_HOME" => ".local/state",
+        "XDG_CACHE_HOME" => ".cache",
+        _ => return None,
+    };
+    Some(HOME_DIR.join(relative).to_string_lossy().into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expand_path_tilde_and_relative() {
+        assert_eq!(expand_path("~").unwrap(), *HOME_DIR);
+        assert_eq!(expand_path("~/.config").unwrap(), HOME_DIR.join(".config"));
+        assert_eq!(expand_path(".config").unwrap(), HOME_DIR.join(".config"));
+        assert_eq!(expand_path("/etc/xdg").unwrap(), PathBuf::from("/etc/xdg"));
+        // Only a leading `~/` is the home directory
+        assert_eq!(expand_path("~other").unwrap(), HOME_DIR.join("~other"));
+    }
+
+    #[test]
+    fn expand_path_variables() {
+        let path = env::var("PATH").unwrap();
+        assert_eq!(
+            expand_path("/x/$PATH/y").unwrap(),
+            PathBuf::from(format!("/x/{path}/y"))
+        );
+        assert_eq!(
+            expand_path("/x/${PATH}y").unwrap(),
+            PathBuf::from(format!("/x/{path}y"))
+        );
+        assert_eq!(expand_path("/a$/b").unwrap(), PathBuf::from("/a$/b"));
+    }
+
+    #[test]
+    fn expand_path_xdg_fallback() {
+        let expected = match env::var("XDG_CACHE_HOME") {
+            Ok(value) if !value.is_empty() => PathBuf::from(value),
+            _ => HOME_DIR.join(".cache"),
+        };
+        assert_eq!(expand_path("$XDG_CACHE_HOME").unwrap(), expected);
+    }
+
+    #[test]
+    fn expand_path_rejects_unset_variable() {
+        assert!(matches!(
+            expand_path("$DOTTIE_SURELY_UNSET_VAR/x"),
+            Err(DirError::UnsetEnvVar(_, name)) if name == "DOTTIE_SURELY_UNSET_VAR"
+        ));
+    }
+}
@@ -0,0 +1,107 @@
+use std::time::Duration;
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum DurationError {
+    #[error(
+        "Malformed duration `{0}`, expected numbers with a unit of `s`, `m`, `h` or `d`, e.g. `1h30m`"
+    )]
+    Malformed(String),
+}
+
+/// Parse a duration like `90`, `30s`, `5m`, `1h30m` or `30d`, a plain number is seconds
+pub fn parse(raw: &str) -> Result<Duration, DurationError> {
+    let malformed = || DurationError::Malformed(raw.to_owned());
+    let mut rest = raw.trim();
+    if rest.is_empty() {
+        return Err(malformed());
+    }
+    if rest.chars().all(|c| c.is_ascii_digit()) {
+        return Ok(Duration::from_secs(rest.parse().map_err(|_| malformed())?));
+    }
+
+    let mut secs: u64 = 0;
+    while !rest.is_empty() {
+        let split = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(malformed)?;
+        let number: u64 = rest[..split].parse().map_err(|_| malformed())?;
+        let scale = match rest[split..].chars().next() {
+            Some('s') => 1,
+            Some('m') => 60,
+            Some('h') => 60 * 60,
+            Some('d') => 24 * 60 * 60,
+            _ => return Err(malformed()),
+        };
+        secs = number
+            .checked_mul(scale)
+            .and_then(|part| secs.checked_add(part))
+            .ok_or_else(malformed)?;
+        rest = &rest[split + 1..];
+    }
+    Ok(Duration::from_secs(secs))
+}
+
+/// Display a duration the way `parse` reads it, e.g. `1h30m`
+pub fn display(duration: Duration) -> String {
+    let mut secs = duration.as_secs();
+    let mut out = String::new();
+    for (unit, scale) in [("d", 24 * 60 * 60), ("h", 60 * 60), ("m", 60)] {
+        if secs >= scale {
+            out.push_str(&format!("{}{unit}", secs / scale));
+            secs %= scale;
+        }
+    }
+    if secs > 0 || out.is_empty() {
+        out.push_str(&format!("{secs}s"));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_single_units() {
+        assert_eq!(parse("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse("30s").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse("5m").unwrap(), Duration::from_secs(5 * 60));
+        assert_eq!(parse(" 2h ").unwrap(), Duration::from_secs(2 * 60 * 60));
+        assert_eq!(
+            parse("30d").unwrap(),
+            Duration::from_secs(30 * 24 * 60 * 60)
+        );
+    }
+
+    #[test]
+    fn parse_combined_units() {
+        assert_eq!(parse("1h30m").unwrap(), Duration::from_secs(90 * 60));
+        assert_eq!(parse("1d2h3m4s").unwrap(), Duration::from_secs(93784));
+    }
+
+    #[test]
+    fn parse_rejects_malformed() {
+        for raw in [
+            "",
+            "h",
+            "5x",
+            "1h30",
+            "-5m",
+            "1.5h",
+            "99999999999999999999d",
+        ] {
+            assert!(parse(raw).is_err(), "{raw:?} should not parse");
+        }
+    }
+
+    #[test]
+    fn display_reads_back() {
+        for secs in [0, 59, 90, 3600, 5400, 93784] {
+            let duration = Duration::from_secs(secs);
+            assert_eq!(parse(&display(duration)).unwrap(), duration);
+        }
+        assert_eq!(display(Duration::from_secs(5400)), "1h30m");
+    }
+}
@@ -0,0 +1,38 @@
+use std::process::ExitCode;
+
+/// What went wrong in a command, each has its own exit code as documented in the README.
+///
+/// Ordered by severity, the most severe failure decides the exit code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Failure {
+    /// A named package or a dependency doesn't exist, or dependencies form a cycle
+    PackageMissing,
+    /// A file could not be linked, copied or rendered
+    LinkFailed,
+    /// A script exited non-zero or timed out
+    ScriptFailed,
+    /// Destinations are not what linking would make of them, with `status --check`
+    Drifted,
+    /// Ctrl-C aborted the run
+    Interrupted,
+}
+
+impl Failure {
+    pub fn code(self) -> u8 {
+        match self {
+            Failure::PackageMissing => 3,
+            Failure::LinkFailed => 4,
+            Failure::ScriptFailed => 5,
+            Failure::Drifted => 6,
+            Failure::Interrupted => 130,
+        }
+    }
+}
+
+/// The exit code for the most severe of the failures, success if there are none
+pub fn code_of(failures: impl IntoIterator<Item = Failure>) -> ExitCode {
+    failures
+        .into_iter()
+        .max()
+        .map_or(ExitCode::SUCCESS, |failure| ExitCode::from(failure.code()))
+}
@@ -0,0 +1,42 @@
+use std::{env, fs};
+
+use nix::unistd::{self, User};
+
+/// Facts about the machine dottie is running on
+#[derive(Debug, Clone)]
+pub struct Host {
+    pub hostname: String,
+    pub os: String,
+    /// The `ID` of `/etc/os-release`, empty if unknown
+    pub distro: String,
+    pub arch: String,
+    pub user: String,
+}
+
+impl Host {
+    pub fn current() -> Self {
+        Self {
+            hostname: unistd::gethostname()
+                .ok()
+                .and_then(|name| name.into_string().ok())
+                .unwrap_or_default(),
+            os: env::consts::OS.to_owned(),
+            distro: distro().unwrap_or_default(),
+            arch: env::consts::ARCH.to_owned(),
+            user: User::from_uid(unistd::getuid())
+                .ok()
+                .flatten()
+                .map(|user| user.name)
+                .or_else(|| env::var("USER").ok())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+fn distro() -> Option<String> {
+    let content = fs::read_to_string("/etc/os-release").ok()?;
+    content.lines().find_map(|line| {
+        let id = line.strip_prefix("ID=")?;
+        Some(id.trim_matches(|c| c == '"' || c == '\'').to_owned())
+    })
+}
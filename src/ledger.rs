@@ -0,0 +1,255 @@
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+use log::trace;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::dir::{Dir, ensure_exists, get};
+
+pub static LINK_LEDGER_FILE: &str = "links.toml";
+pub static RUN_LEDGER_FILE: &str = "runs.toml";
+
+#[derive(Debug, Error)]
+pub enum LedgerError {
+    #[error("Failed to read ledger {0}: {1}")]
+    CannotRead(PathBuf, io::Error),
+    #[error("Malformed ledger {0}: {1}")]
+    Malformed(PathBuf, toml::de::Error),
+    #[error("Failed to serialize ledger: {0}")]
+    CannotSerialize(toml::ser::Error),
+    #[error("Failed to write ledger {0}: {1}")]
+    CannotWrite(PathBuf, io::Error),
+}
+
+/// Every link dottie has made and not yet removed, persisted under the state directory
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct LinkLedger {
+    #[serde(default, rename = "link")]
+    records: Vec<LinkRecord>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkMode {
+    /// The destination is a symlink to the source
+    #[default]
+    Symlink,
+    /// The destination is a copy of the source
+    Copy,
+    /// The destination is the rendered source template
+    Template,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LinkRecord {
+    /// The package the linked file belongs to
+    pub pkg_name: String,
+    /// The file inside the package
+    pub src_path: PathBuf,
+    /// The link pointing to the file
+    pub dest_path: PathBuf,
+    /// When the link was made
+    pub created_at: DateTime<Utc>,
+    /// Where the occupied destination was moved to, if forced
+    pub backup_path: Option<PathBuf>,
+    /// How the destination was made
+    #[serde(default)]
+    pub mode: LinkMode,
+    /// Content hash of the destination when it was made, for copies and templates
+    pub hash: Option<String>,
+}
+
+impl LinkRecord {
+    pub fn symlink(
+        pkg_name: &str,
+        src_path: &Path,
+        dest_path: &Path,
+        backup_path: Option<PathBuf>,
+    ) -> Self {
+        Self {
+            pkg_name: pkg_name.to_owned(),
+            src_path: src_path.to_owned(),
+            dest_path: dest_path.to_owned(),
+            created_at: Utc::now(),
+            backup_path,
+            mode: LinkMode::Symlink,
+            hash: None,
+        }
+    }
+
+    /// Record of a destination written with content, either copied or rendered
+    pub fn written(
+        pkg_name: &str,
+        src_path: &Path,
+        dest_path: &Path,
+        backup_path: Option<PathBuf>,
+        mode: LinkMode,
+        hash: String,
+    ) -> Self {
+        Self {
+            pkg_name: pkg_name.to_owned(),
+            src_path: src_path.to_owned(),
+            dest_path: dest_path.to_owned(),
+            created_at: Utc::now(),
+            backup_path,
+            mode,
+            hash: Some(hash),
+        }
+    }
+
+    /// Whether the destination is still what dottie made of it
+    pub fn is_intact(&self) -> bool {
+        match self.mode {
+            LinkMode::Symlink => {
+                self.dest_path.is_symlink()
+                    && self
+                        .dest_path
+                        .read_link()
+                        .is_ok_and(|path| path == self.src_path)
+            }
+            LinkMode::Copy | LinkMode::Template => {
+                !self.dest_path.is_symlink()
+                    && self.dest_path.is_file()
+                    && content_hash(&self.dest_path).ok() == self.hash
+            }
+        }
+    }
+}
+
+impl LinkLedger {
+    pub fn path() -> PathBuf {
+        get(Dir::State).join(LINK_LEDGER_FILE)
+    }
+
+    /// Load the ledger, an absent ledger is an empty one
+    pub fn load() -> eyre::Result<Self> {
+        let path = Self::path();
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                trace!("No link ledger at {:?}, starting empty", path);
+                return Ok(Self::default());
+            }
+            Err(e) => return Err(LedgerError::CannotRead(path, e).into()),
+        };
+        Ok(toml::from_str(&content).map_err(|e| LedgerError::Malformed(path, e))?)
+    }
+
+    pub fn save(&self) -> eyre::Result<()> {
+        ensure_exists(Dir::State)?;
+        let path = Self::path();
+        let content = toml::to_string(self).map_err(LedgerError::CannotSerialize)?;
+        fs::write(&path, content).map_err(|e| LedgerError::CannotWrite(path, e))?;
+        Ok(())
+    }
+
+    pub fn records(&self) -> &[LinkRecord] {
+        &self.records
+    }
+
+    pub fn find(&self, dest_path: &Path) -> Option<&LinkRecord> {
+        self.records
+            .iter()
+            .find(|record| record.dest_path == dest_path)
+    }
+
+    /// Record a link, replacing any previous record of the same destination
+    pub fn record(&mut self, record: LinkRecord) {
+        self.remove(&record.dest_path);
+        self.records.push(record);
+    }
+
+    /// Forget the backup wherever it is recorded, once it was restored or deleted
+    pub fn forget_backup(&mut self, backup_path: &Path) {
+        for record in &mut self.records {
+            if record.backup_path.as_deref() == Some(backup_path) {
+                record.backup_path = None;
+            }
+        }
+    }
+
+    pub fn remove(&mut self, dest_path: &Path) -> Option<LinkRecord> {
+        let index = self
+            .records
+            .iter()
+            .position(|record| record.dest_path == dest_path)?;
+        Some(self.records.remove(index))
+    }
+}
+
+// LYN: Run Ledger
+
+/// Last successful run of the run-once and run-on-change scripts, by package then script name
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct RunLedger {
+    #[serde(flatten)]
+    records: BTreeMap<String, BTreeMap<String, RunRecord>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunRecord {
+    /// When the script last finished successfully
+    pub ran_at: DateTime<Utc>,
+    /// Content hash of the script as it was run
+    pub hash: String,
+}
+
+impl RunLedger {
+    pub fn path() -> PathBuf {
+        get(Dir::State).join(RUN_LEDGER_FILE)
+    }
+
+    /// Load the ledger, an absent ledger is an empty one
+    pub fn load() -> eyre::Result<Self> {
+        let path = Self::path();
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                trace!("No run ledger at {:?}, starting empty", path);
+                return Ok(Self::default());
+            }
+            Err(e) => return Err(LedgerError::CannotRead(path, e).into()),
+        };
+        Ok(toml::from_str(&content).map_err(|e| LedgerError::Malformed(path, e))?)
+    }
+
+    pub fn save(&self) -> eyre::Result<()> {
+        ensure_exists(Dir::State)?;
+        let path = Self::path();
+        let content = toml::to_string(self).map_err(LedgerError::CannotSerialize)?;
+        fs::write(&path, content).map_err(|e| LedgerError::CannotWrite(path, e))?;
+        Ok(())
+    }
+
+    pub fn find(&self, pkg_name: &str, script_name: &str) -> Option<&RunRecord> {
+        self.records.get(pkg_name)?.get(script_name)
+    }
+
+    pub fn record(&mut self, pkg_name: &str, script_name: &str, hash: String) {
+        self.records.entry(pkg_name.to_owned()).or_default().insert(
+            script_name.to_owned(),
+            RunRecord {
+                ran_at: Utc::now(),
+                hash,
+            },
+        );
+    }
+}
+
+/// Hex encoded SHA-256 of a file's content
+pub fn content_hash(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Hex encoded SHA-256 of in-memory content
+pub fn bytes_hash(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
@@ -1,20 +1,33 @@
-use std::convert::Infallible;
+use std::{convert::Infallible, process::ExitCode};
 
 use clap::Parser;
 use env_logger::Target;
 use log::trace;
 
 use crate::{
-    cli::{CliArg, info, link, run},
+    cli::{CliArg, info, link, run, unlink},
     dir::{Dir, ensure_exists},
 };
 
+mod backup;
 mod cli;
+mod condition;
 mod dir;
+mod duration;
+mod exit;
+mod host;
+mod ledger;
+mod manifest;
+mod pattern;
+mod pkg;
+mod process;
+mod profile;
+mod template;
+mod variant;
 
 // LYN: Main
 
-fn main() -> eyre::Result<()> {
+fn main() -> eyre::Result<ExitCode> {
     color_eyre::install()?;
     init_logger()?;
     ensure_exists(Dir::App)?;
@@ -22,20 +35,49 @@ fn main() -> eyre::Result<()> {
     let arg = CliArg::parse();
     trace!("Parsed argument: {:?}", arg);
 
-    match arg {
+    let code = match arg {
         CliArg::Run(arg) => run::main(&arg)?,
         CliArg::Link(arg) => link::main(&arg)?,
-        CliArg::Info(arg) => info::main(&arg)?,
-    }
+        CliArg::Unlink(arg) => {
+            unlink::main(&arg)?;
+            ExitCode::SUCCESS
+        }
+        CliArg::Backup(arg) => {
+            cli::backup::main(&arg)?;
+            ExitCode::SUCCESS
+        }
+        CliArg::Status(arg) => cli::status::main(&arg)?,
+        CliArg::Adopt(arg) => {
+            cli::adopt::main(&arg)?;
+            ExitCode::SUCCESS
+        }
+        CliArg::New(arg) => {
+            cli::new::main(&arg)?;
+            ExitCode::SUCCESS
+        }
+        CliArg::List(arg) => {
+            cli::list::main(&arg)?;
+            ExitCode::SUCCESS
+        }
+        CliArg::Profile(arg) => {
+            cli::profile::main(&arg)?;
+            ExitCode::SUCCESS
+        }
+        CliArg::Info(arg) => {
+            info::main(&arg)?;
+            ExitCode::SUCCESS
+        }
+    };
 
-    Ok(())
+    Ok(code)
 }
 
 // LYN: Helpers
 
 fn init_logger() -> Result<(), Infallible> {
+    // Kept off stdout, which holds the summary alone, e.g. for `--format json`
     env_logger::Builder::from_default_env()
-        .target(Target::Stdout)
+        .target(Target::Stderr)
         .init();
     Ok(())
 }
@@ -0,0 +1,223 @@
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use log::trace;
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::{
+    condition::Condition,
+    dir::{Dir, HOME_DIR, expand_path, get, is_valid_pkg_name},
+    duration,
+    ledger::LinkMode,
+};
+
+pub static MANIFEST_FILE: &str = "dottie.toml";
+
+#[derive(Debug, Error)]
+pub enum ManifestError {
+    #[error("Failed to read manifest {0}: {1}")]
+    CannotRead(PathBuf, io::Error),
+    #[error("Malformed manifest {0}: {1}")]
+    Malformed(PathBuf, toml::de::Error),
+    #[error("Invalid value for `{key}` in manifest {path}: {reason}")]
+    InvalidValue {
+        path: PathBuf,
+        key: String,
+        reason: String,
+    },
+}
+
+/// Optional package metadata, read from `<pkg>/dottie.toml`
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Manifest {
+    /// Where the manifest was loaded from
+    #[serde(skip)]
+    pub path: PathBuf,
+    /// What the package is for
+    pub description: Option<String>,
+    /// Where the package files are linked into, defaults to the home directory
+    pub target: Option<String>,
+    /// Packages that must be applied before this one
+    pub dependencies: Vec<String>,
+    /// Labels to select the package by
+    pub tags: Vec<String>,
+    /// How files are made at their destination, unless a pattern or extension says otherwise
+    pub mode: LinkMode,
+    /// Gitignore-style patterns of files to leave out, in addition to `.dottieignore`
+    pub ignore: Vec<String>,
+    /// Gitignore-style patterns of files to copy, in addition to `.dottiecopy`
+    pub copy: Vec<String>,
+    /// Which package links a destination that several packages ship, the highest wins
+    pub priority: i32,
+    /// Packages whose files this package links in their stead, regardless of priority
+    pub overrides: Vec<String>,
+    /// Settings for running the package scripts
+    pub scripts: ScriptsManifest,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScriptsManifest {
+    /// Extra environment variables the scripts run with
+    pub env: BTreeMap<String, String>,
+    /// Scripts to run first and in this order, the rest follow sorted naturally by name
+    pub order: Vec<String>,
+    /// Scripts never run again once they succeeded
+    pub once: Vec<String>,
+    /// Scripts run again only when their content changed since they last succeeded
+    pub on_change: Vec<String>,
+    /// How long a script may run before it is stopped, by script name, e.g. `"5m"`
+    pub timeout: BTreeMap<String, String>,
+    /// Conditions that must hold for a script to run, by script name, e.g. `["has.systemctl"]`
+    pub when: BTreeMap<String, Vec<String>>,
+    /// Skip the remaining scripts of the package once one fails
+    pub fail_fast: bool,
+}
+
+/// When a script is run again
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rerun {
+    Always,
+    Never,
+    OnChange,
+}
+
+impl ScriptsManifest {
+    pub fn timeout_of(&self, script_name: &str) -> Option<Duration> {
+        duration::parse(self.timeout.get(script_name)?).ok()
+    }
+
+    pub fn rerun_of(&self, script_name: &str) -> Rerun {
+        let script_name = script_name.to_owned();
+        if self.once.contains(&script_name) {
+            Rerun::Never
+        } else if self.on_change.contains(&script_name) {
+            Rerun::OnChange
+        } else {
+            Rerun::Always
+        }
+    }
+
+    /// Conditions the manifest puts on the given script
+    pub fn conditions_of(&self, script_name: &str) -> Vec<Condition> {
+        self.when
+            .get(script_name)
+            .into_iter()
+            .flatten()
+            .filter_map(|raw| Condition::parse(raw).ok())
+            .collect()
+    }
+}
+
+impl Manifest {
+    /// Load the manifest of a package, an absent manifest is an empty one
+    pub fn load(pkg_name: &str) -> eyre::Result<Self> {
+        let path = get(Dir::Pkg {
+            pkg_name: pkg_name.to_owned(),
+        })
+        .join(MANIFEST_FILE);
+        let mut manifest = Self::load_from(&path)?;
+        manifest.path = path;
+        manifest.validate(pkg_name)?;
+        Ok(manifest)
+    }
+
+    fn load_from(path: &Path) -> eyre::Result<Self> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                trace!("No manifest at {:?}", path);
+                return Ok(Self::default());
+            }
+            Err(e) => return Err(ManifestError::CannotRead(path.to_owned(), e).into()),
+        };
+        Ok(toml::from_str(&content).map_err(|e| ManifestError::Malformed(path.to_owned(), e))?)
+    }
+
+    fn validate(&self, pkg_name: &str) -> Result<(), ManifestError> {
+        self.target_dir()?;
+        if self.mode == LinkMode::Template {
+            return Err(self.invalid(
+                "mode",
+                "templates are picked by extension, expected `symlink` or `copy`",
+            ));
+        }
+        for dep in &self.dependencies {
+            if !is_valid_pkg_name(dep) {
+                return Err(self.invalid("dependencies", format!("`{dep}` is not a package name")));
+            }
+            if dep == pkg_name {
+                return Err(self.invalid("dependencies", "a package cannot depend on itself"));
+            }
+        }
+        for overridden in &self.overrides {
+            if !is_valid_pkg_name(overridden) {
+                return Err(
+                    self.invalid("overrides", format!("`{overridden}` is not a package name"))
+                );
+            }
+            if overridden == pkg_name {
+                return Err(self.invalid("overrides", "a package cannot override itself"));
+            }
+        }
+        for (index, script_name) in self.scripts.order.iter().enumerate() {
+            if self.scripts.order[..index].contains(script_name) {
+                return Err(self.invalid(
+                    "scripts.order",
+                    format!("`{script_name}` is listed more than once"),
+                ));
+            }
+        }
+        for script_name in &self.scripts.once {
+            if self.scripts.on_change.contains(script_name) {
+                return Err(self.invalid(
+                    "scripts.on_change",
+                    format!("`{script_name}` is already run once"),
+                ));
+            }
+        }
+        for (script_name, timeout) in &self.scripts.timeout {
+            duration::parse(timeout).map_err(|e| {
+                self.invalid(&format!("scripts.timeout.{script_name}"), e.to_string())
+            })?;
+        }
+        for (script_name, conditions) in &self.scripts.when {
+            for raw in conditions {
+                Condition::parse(raw).map_err(|e| {
+                    self.invalid(&format!("scripts.when.{script_name}"), e.to_string())
+                })?;
+            }
+        }
+        for tag in &self.tags {
+            if tag.is_empty() || tag.contains(char::is_whitespace) {
+                return Err(self.invalid("tags", format!("`{tag}` is empty or has whitespace")));
+            }
+        }
+        Ok(())
+    }
+
+    /// Error for the given key of this manifest
+    pub fn invalid(&self, key: &str, reason: impl Into<String>) -> ManifestError {
+        ManifestError::InvalidValue {
+            path: self.path.clone(),
+            key: key.to_owned(),
+            reason: reason.into(),
+        }
+    }
+
+    /// The directory the package files are linked into
+    pub fn target_dir(&self) -> Result<PathBuf, ManifestError> {
+        Ok(match &self.target {
+            Some(target) => {
+                expand_path(target).map_err(|e| self.invalid("target", e.to_string()))?
+            }
+            None => HOME_DIR.clone(),
+        })
+    }
+}
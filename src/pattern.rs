@@ -0,0 +1,122 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use log::trace;
+use thiserror::Error;
+
+use crate::{
+    dir::{Dir, get},
+    manifest::Manifest,
+};
+
+pub static IGNORE_FILE: &str = ".dottieignore";
+pub static COPY_FILE: &str = ".dottiecopy";
+
+#[derive(Debug, Error)]
+pub enum PatternError {
+    #[error("Cannot confirm existence of {0}: {1}")]
+    CannotConfirmFileExistence(PathBuf, io::Error),
+    #[error("Malformed pattern file {0}: {1}")]
+    Malformed(PathBuf, ignore::Error),
+    #[error("Failed to compile patterns rooted at {0}: {1}")]
+    CannotCompile(PathBuf, ignore::Error),
+}
+
+/// Gitignore-style patterns matched against the entries of a package's files directory
+#[derive(Debug)]
+pub struct Patterns {
+    gitignore: Gitignore,
+}
+
+impl Patterns {
+    /// Compile the pattern files that exist, then the manifest patterns under the given key,
+    /// later patterns take precedence over earlier ones
+    pub fn load(
+        root: &Path,
+        pattern_files: &[PathBuf],
+        manifest: &Manifest,
+        key: &str,
+        lines: &[String],
+    ) -> eyre::Result<Self> {
+        let mut builder = GitignoreBuilder::new(root);
+        for file in pattern_files {
+            if !fs::exists(file)
+                .map_err(|e| PatternError::CannotConfirmFileExistence(file.to_owned(), e))?
+            {
+                continue;
+            }
+            trace!("Loading patterns from {:?}", file);
+            if let Some(e) = builder.add(file) {
+                return Err(PatternError::Malformed(file.to_owned(), e).into());
+            }
+        }
+        for line in lines {
+            builder
+                .add_line(Some(manifest.path.clone()), line)
+                .map_err(|e| manifest.invalid(key, format!("`{line}`: {e}")))?;
+        }
+        let gitignore = builder
+            .build()
+            .map_err(|e| PatternError::CannotCompile(root.to_owned(), e))?;
+        Ok(Self { gitignore })
+    }
+
+    /// Patterns of files to leave out when linking, the global ignore file applies to every package
+    pub fn ignore_for(pkg_name: &str, manifest: &Manifest) -> eyre::Result<Self> {
+        Self::load(
+            &get(Dir::Files {
+                pkg_name: pkg_name.to_owned(),
+            }),
+            &[
+                get(Dir::App).join(IGNORE_FILE),
+                get(Dir::Pkg {
+                    pkg_name: pkg_name.to_owned(),
+                })
+                .join(IGNORE_FILE),
+            ],
+            manifest,
+            "ignore",
+            &manifest.ignore,
+        )
+    }
+
+    /// Patterns of files to copy instead of symlink
+    pub fn copy_for(pkg_name: &str, manifest: &Manifest) -> eyre::Result<Self> {
+        Self::load(
+            &get(Dir::Files {
+                pkg_name: pkg_name.to_owned(),
+            }),
+            &[get(Dir::Pkg {
+                pkg_name: pkg_name.to_owned(),
+            })
+            .join(COPY_FILE)],
+            manifest,
+            "copy",
+            &manifest.copy,
+        )
+    }
+
+    /// Whether the path, or any directory containing it, matches
+    pub fn matches(&self, path: &Path) -> bool {
+        self.gitignore
+            .matched_path_or_any_parents(path, path.is_dir() && !path.is_symlink())
+            .is_ignore()
+    }
+
+    /// Whether anything inside the given directory matches
+    pub fn matches_within(&self, dir: &Path) -> io::Result<bool> {
+        for entry in dir.read_dir()? {
+            let path = entry?.path();
+            if self.matches(&path) {
+                return Ok(true);
+            }
+            if path.is_dir() && !path.is_symlink() && self.matches_within(&path)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
@@ -0,0 +1,356 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    ffi::OsString,
+    fmt::{self, Display, Formatter},
+};
+
+use log::{info, trace, warn};
+use owo_colors::OwoColorize;
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::{
+    dir::{Dir, exists, get, not_package},
+    manifest::Manifest,
+    profile::{Machine, PkgOverride, Profile},
+};
+
+#[derive(Debug, Error)]
+pub enum PkgError {
+    #[error("Failed to parse UTF8 OsString: {0:?}")]
+    InvalidUtf8OsString(OsString),
+    #[error("No packages given and no default profile set, see `dottie profile`")]
+    NothingSelected,
+}
+
+/// Names of all packages, sorted
+pub fn all() -> eyre::Result<Vec<String>> {
+    let mut pkg_names = Vec::new();
+    for pkg_entry in get(Dir::App).read_dir()? {
+        let pkg_entry = pkg_entry?;
+        if not_package(&pkg_entry.path()) {
+            info!("Skipping non package entry: {:?}", pkg_entry);
+            continue;
+        }
+        pkg_names.push(
+            pkg_entry
+                .file_name()
+                .into_string()
+                .map_err(PkgError::InvalidUtf8OsString)?,
+        );
+    }
+    pkg_names.sort();
+    Ok(pkg_names)
+}
+
+// LYN: Package Selection
+
+/// Select the packages named, all packages, or those of a profile, falling back to the default
+/// profile of this machine when none is given
+pub fn select(
+    pkgs: &[String],
+    all: bool,
+    profile: Option<&str>,
+    with_deps: bool,
+) -> eyre::Result<Selection> {
+    if all {
+        return resolve(&self::all()?, with_deps);
+    }
+    if !pkgs.is_empty() {
+        return resolve(pkgs, with_deps);
+    }
+
+    let profile_name = match profile {
+        Some(profile_name) => profile_name.to_owned(),
+        None => Machine::load()?.profile.ok_or(PkgError::NothingSelected)?,
+    };
+    info!("Selecting packages of profile `{}`", profile_name);
+    let profile = Profile::load(&profile_name)?;
+    let mut selection = resolve(&profile.packages, with_deps)?;
+    selection.profile = Some(profile_name);
+    selection.overrides = profile.overrides;
+    Ok(selection)
+}
+
+// LYN: Dependency Resolution
+
+/// Packages to process, ordered so that dependencies come first
+#[derive(Debug, Default)]
+pub struct Selection {
+    /// Package names in the order they should be processed
+    pub ordered: Vec<String>,
+    /// Requested package names that do not exist
+    pub non_exist: Vec<String>,
+    /// Packages left out because of their dependencies
+    pub dep_issues: Vec<DepIssue>,
+    /// The profile the packages were selected by
+    pub profile: Option<String>,
+    /// Manifest values overridden by the profile, by package name
+    pub overrides: BTreeMap<String, PkgOverride>,
+}
+
+impl Selection {
+    /// Load the manifest of a selected package, with the profile overrides applied
+    pub fn manifest(&self, pkg_name: &str) -> eyre::Result<Manifest> {
+        let mut manifest = Manifest::load(pkg_name)?;
+        if let Some(over) = self.overrides.get(pkg_name) {
+            trace!("Applying profile overrides to `{}`: {:?}", pkg_name, over);
+            over.apply(&mut manifest);
+        }
+        Ok(manifest)
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "issue", rename_all = "snake_case")]
+pub enum DepIssue {
+    /// The package depends on a package that does not exist
+    Missing { pkg_name: String, dep_name: String },
+    /// The packages depend on each other in a loop
+    Cycle { pkg_names: Vec<String> },
+    /// The package depends on a package that was left out
+    Blocked { pkg_name: String, dep_name: String },
+}
+
+impl Display for DepIssue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            DepIssue::Missing { pkg_name, dep_name } => write!(
+                f,
+                "Package {} skipped, it depends on {} which doesn't exist",
+                format!("`{}`", pkg_name).yellow(),
+                format!("`{}`", dep_name).yellow(),
+            ),
+            DepIssue::Cycle { pkg_names } => write!(
+                f,
+                "Packages {} skipped, they form a dependency cycle",
+                pkg_names
+                    .iter()
+                    .chain(pkg_names.first())
+                    .map(|pkg_name| format!("`{}`", pkg_name).yellow().to_string())
+                    .collect::<Vec<_>>()
+                    .join(" -> "),
+            ),
+            DepIssue::Blocked { pkg_name, dep_name } => write!(
+                f,
+                "Package {} skipped, it depends on {} which was skipped",
+                format!("`{}`", pkg_name).yellow(),
+                format!("`{}`", dep_name).yellow(),
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Mark {
+    Visiting,
+    Done { ok: bool },
+}
+
+/// Order the requested packages topologically, pulling in their dependencies if asked to.
+///
+/// Without pulling in dependencies, only dependencies among the requested packages are honoured.
+pub fn resolve(requested: &[String], with_deps: bool) -> eyre::Result<Selection> {
+    resolve_with(requested, with_deps, |pkg_name| {
+        if !exists(Dir::Pkg {
+            pkg_name: pkg_name.to_owned(),
+        })? {
+            return Ok(None);
+        }
+        Ok(Some(Manifest::load(pkg_name)?.dependencies))
+    })
+}
+
+/// Order the packages by the dependencies `deps_of` gives, `None` for a package that doesn't exist
+fn resolve_with<F>(requested: &[String], with_deps: bool, deps_of: F) -> eyre::Result<Selection>
+where
+    F: FnMut(&str) -> eyre::Result<Option<Vec<String>>>,
+{
+    let mut resolver = Resolver {
+        with_deps,
+        deps_of,
+        deps: HashMap::new(),
+        wanted: BTreeSet::new(),
+        marks: HashMap::new(),
+        path: Vec::new(),
+        in_cycle: BTreeSet::new(),
+        selection: Selection::default(),
+    };
+    let mut wanted = Vec::new();
+    for pkg_name in requested {
+        if resolver.deps(pkg_name)?.is_none() {
+            warn!("Package `{}` does not exist", pkg_name);
+            resolver.selection.non_exist.push(pkg_name.to_owned());
+        } else if !wanted.contains(pkg_name) {
+            wanted.push(pkg_name.to_owned());
+        }
+    }
+
+    resolver.wanted = wanted.iter().cloned().collect();
+    for pkg_name in &wanted {
+        resolver.visit(pkg_name)?;
+    }
+
+    Ok(resolver.selection)
+}
+
+struct Resolver<F> {
+    with_deps: bool,
+    deps_of: F,
+    /// Dependencies of the packages looked up so far, `None` for those that don't exist
+    deps: HashMap<String, Option<Vec<String>>>,
+    wanted: BTreeSet<String>,
+    marks: HashMap<String, Mark>,
+    /// Packages being visited, from the outermost
+    path: Vec<String>,
+    /// Packages already reported as part of a cycle
+    in_cycle: BTreeSet<String>,
+    selection: Selection,
+}
+
+impl<F> Resolver<F>
+where
+    F: FnMut(&str) -> eyre::Result<Option<Vec<String>>>,
+{
+    fn deps(&mut self, pkg_name: &str) -> eyre::Result<Option<Vec<String>>> {
+        if let Some(deps) = self.deps.get(pkg_name) {
+            return Ok(deps.clone());
+        }
+        let deps = (self.deps_of)(pkg_name)?;
+        self.deps.insert(pkg_name.to_owned(), deps.clone());
+        Ok(deps)
+    }
+
+    /// Visit the package after its dependencies, returns whether it can be processed
+    fn visit(&mut self, pkg_name: &str) -> eyre::Result<bool> {
+        match self.marks.get(pkg_name) {
+            Some(Mark::Done { ok }) => return Ok(*ok),
+            Some(Mark::Visiting) => {
+                let start = self
+                    .path
+                    .iter()
+                    .position(|visiting| visiting == pkg_name)
+                    .unwrap_or_default();
+                let pkg_names = self.path[start..].to_vec();
+                warn!("Dependency cycle found: {:?}", pkg_names);
+                self.in_cycle.extend(pkg_names.iter().cloned());
+                self.selection
+                    .dep_issues
+                    .push(DepIssue::Cycle { pkg_names });
+                return Ok(false);
+            }
+            None => {}
+        }
+
+        trace!("Resolving dependencies of `{}`", pkg_name);
+        self.marks.insert(pkg_name.to_owned(), Mark::Visiting);
+        self.path.push(pkg_name.to_owned());
+
+        let mut ok = true;
+        for dep_name in self.deps(pkg_name)?.unwrap_or_default() {
+            if !self.with_deps && !self.wanted.contains(&dep_name) {
+                trace!("Not pulling in `{}` for `{}`", dep_name, pkg_name);
+                continue;
+            }
+            if self.deps(&dep_name)?.is_none() {
+                warn!("Package `{}` depends on missing `{}`", pkg_name, dep_name);
+                self.selection.dep_issues.push(DepIssue::Missing {
+                    pkg_name: pkg_name.to_owned(),
+                    dep_name,
+                });
+                ok = false;
+                continue;
+            }
+            if !self.visit(&dep_name)? {
+                if !self.in_cycle.contains(pkg_name) {
+                    self.selection.dep_issues.push(DepIssue::Blocked {
+                        pkg_name: pkg_name.to_owned(),
+                        dep_name,
+                    });
+                }
+                ok = false;
+            }
+        }
+
+        self.path.pop();
+        self.marks.insert(pkg_name.to_owned(), Mark::Done { ok });
+        if ok {
+            self.selection.ordered.push(pkg_name.to_owned());
+        }
+        Ok(ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Resolve against packages given as `(name, dependencies)`
+    fn resolve_in(graph: &[(&str, &[&str])], requested: &[&str], with_deps: bool) -> Selection {
+        let graph: HashMap<_, _> = graph.iter().copied().collect();
+        let requested: Vec<_> = requested.iter().map(|name| (*name).to_owned()).collect();
+        resolve_with(&requested, with_deps, |pkg_name| {
+            Ok(graph
+                .get(pkg_name)
+                .map(|deps| deps.iter().map(|dep| (*dep).to_owned()).collect()))
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn resolve_orders_dependencies_first() {
+        let graph: &[(&str, &[&str])] = &[
+            ("app", &["lib", "shell"]),
+            ("lib", &["shell"]),
+            ("shell", &[]),
+        ];
+        let selection = resolve_in(graph, &["app"], true);
+        assert_eq!(selection.ordered, ["shell", "lib", "app"]);
+        assert!(selection.dep_issues.is_empty());
+
+        let selection = resolve_in(graph, &["app", "shell"], false);
+        assert_eq!(selection.ordered, ["shell", "app"]);
+    }
+
+    #[test]
+    fn resolve_reports_missing() {
+        let graph: &[(&str, &[&str])] = &[("app", &["gone"]), ("ok", &[])];
+        let selection = resolve_in(graph, &["nope", "app", "ok"], true);
+        assert_eq!(selection.non_exist, ["nope"]);
+        assert_eq!(selection.ordered, ["ok"]);
+        assert!(matches!(
+            &selection.dep_issues[..],
+            [DepIssue::Missing { pkg_name, dep_name }] if pkg_name == "app" && dep_name == "gone"
+        ));
+    }
+
+    #[test]
+    fn resolve_reports_cycle_once_and_blocks_dependents() {
+        let graph: &[(&str, &[&str])] = &[
+            ("a", &["b"]),
+            ("b", &["c"]),
+            ("c", &["a"]),
+            ("top", &["a"]),
+            ("free", &[]),
+        ];
+        let selection = resolve_in(graph, &["top", "free"], true);
+        assert_eq!(selection.ordered, ["free"]);
+        assert!(matches!(
+            &selection.dep_issues[..],
+            [
+                DepIssue::Cycle { pkg_names },
+                DepIssue::Blocked { pkg_name, dep_name },
+            ] if *pkg_names == ["a", "b", "c"] && pkg_name == "top" && dep_name == "a"
+        ));
+    }
+
+    #[test]
+    fn resolve_self_dependency_is_a_cycle() {
+        let selection = resolve_in(&[("me", &["me"])], &["me"], true);
+        assert!(selection.ordered.is_empty());
+        assert!(matches!(
+            &selection.dep_issues[..],
+            [DepIssue::Cycle { pkg_names }] if *pkg_names == ["me"]
+        ));
+    }
+}
@@ -0,0 +1,263 @@
+use std::{
+    io::{self, IsTerminal},
+    os::unix::process::{CommandExt, ExitStatusExt},
+    process::{Command, ExitStatus},
+    sync::{Arc, Barrier, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::{info, trace, warn};
+use nix::{
+    sys::{
+        signal::{SigSet, Signal, killpg},
+        wait::{WaitPidFlag, WaitStatus, waitpid},
+    },
+    unistd::{Pid, getpgrp, tcgetpgrp, tcsetpgrp},
+};
+use signal_hook::{consts::SIGINT, iterator::Signals};
+
+/// How long a timed out script has to exit after SIGTERM before it gets SIGKILL
+static TERM_GRACE_PERIOD: Duration = Duration::from_secs(5);
+/// How soon a second Ctrl-C must follow the previous one to abort the whole run
+static INTERRUPT_GRACE_PERIOD: Duration = Duration::from_secs(2);
+static WAIT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Debug)]
+pub enum Waited {
+    Exited(ExitStatus),
+    /// Terminated after running too long, `killed` if it outlived the grace period
+    TimedOut {
+        after: Duration,
+        killed: bool,
+    },
+}
+
+#[derive(Debug, Default)]
+struct Interrupts {
+    /// Process group of the running script
+    pgid: Option<Pid>,
+    /// Ctrl-C presses since the running script started
+    presses: u32,
+    /// When Ctrl-C was last pressed
+    last_press: Option<Instant>,
+    /// Whether the whole run should stop
+    abort: bool,
+}
+
+/// Runs scripts each in their own process group, so signals reach everything a script started.
+///
+/// Ctrl-C is forwarded to the running script's group, escalating from SIGINT to SIGTERM to
+/// SIGKILL on repeated presses, and whatever remains of the group is killed once the script
+/// exits. A press while no script runs, or one following the previous within
+/// `INTERRUPT_GRACE_PERIOD`, aborts the run.
+///
+/// A script handed the terminal gets Ctrl-C straight from it, so dying of SIGINT then counts
+/// as a press, the rest of its group is killed and a quick press after it aborts the run.
+pub struct Supervisor {
+    interrupts: Arc<Mutex<Interrupts>>,
+    handle: signal_hook::iterator::Handle,
+}
+
+impl Supervisor {
+    pub fn start() -> io::Result<Self> {
+        let interrupts = Arc::new(Mutex::new(Interrupts::default()));
+        let mut signals = Signals::new([SIGINT])?;
+        let handle = signals.handle();
+        let started = Arc::new(Barrier::new(2));
+        {
+            let interrupts = Arc::clone(&interrupts);
+            let started = Arc::clone(&started);
+            thread::spawn(move || {
+                let id = thread::current().id();
+                info!("Interrupt forwarder {:?} started", id);
+                started.wait();
+                for _ in signals.forever() {
+                    interrupts.lock().unwrap().press();
+                }
+                info!("Interrupt forwarder {:?} stopped", id);
+            });
+        }
+        started.wait();
+        Ok(Self { interrupts, handle })
+    }
+
+    /// Whether Ctrl-C asked for the whole run to stop
+    pub fn aborted(&self) -> bool {
+        self.interrupts.lock().unwrap().abort
+    }
+
+    /// Run the command in a new process group until it exits or the timeout passes
+    pub fn run(&self, command: &mut Command, timeout: Option<Duration>) -> io::Result<Waited> {
+        let child = command.process_group(0).spawn()?;
+        let pgid = Pid::from_raw(child.id() as i32);
+        {
+            let mut interrupts = self.interrupts.lock().unwrap();
+            interrupts.pgid = Some(pgid);
+            interrupts.presses = 0;
+        }
+
+        let mut foreground = false;
+        let waited = self.wait(pgid, timeout, &mut foreground);
+
+        let interrupted = {
+            let mut interrupts = self.interrupts.lock().unwrap();
+            interrupts.pgid = None;
+            interrupts.presses > 0
+        };
+        if interrupted || matches!(waited, Ok(Waited::TimedOut { .. })) {
+            // Whatever the script left behind was asked to stop as well
+            trace!("Killing what remains of {:?}", pgid);
+            let _ = killpg(pgid, Signal::SIGKILL);
+        }
+        if foreground {
+            reclaim_terminal();
+        }
+        waited
+    }
+
+    fn wait(
+        &self,
+        pgid: Pid,
+        timeout: Option<Duration>,
+        foreground: &mut bool,
+    ) -> io::Result<Waited> {
+        let started = Instant::now();
+        let mut terminated_at = None;
+        loop {
+            match waitpid(pgid, Some(WaitPidFlag::WNOHANG | WaitPidFlag::WUNTRACED))? {
+                WaitStatus::StillAlive => {}
+                WaitStatus::Stopped(_, Signal::SIGTTIN | Signal::SIGTTOU) if !*foreground => {
+                    // The script wants the terminal, hand it over like a shell's `fg`
+                    trace!("Handing the terminal to {:?}", pgid);
+                    *foreground = hand_terminal(pgid);
+                    let _ = killpg(pgid, Signal::SIGCONT);
+                }
+                WaitStatus::Stopped(..) => {}
+                status => {
+                    if *foreground && died_of_interrupt(status) {
+                        // Ctrl-C went from the terminal to the script, dottie never saw it
+                        self.interrupts.lock().unwrap().pressed_directly();
+                    }
+                    let status = exit_status(status);
+                    return Ok(match (terminated_at, timeout) {
+                        (Some(_), Some(after)) => Waited::TimedOut {
+                            after,
+                            killed: false,
+                        },
+                        _ => Waited::Exited(status),
+                    });
+                }
+            }
+
+            if let Some(timeout) = timeout {
+                match terminated_at {
+                    None if started.elapsed() >= timeout => {
+                        trace!("Terminating {:?} after {:?}", pgid, timeout);
+                        let _ = killpg(pgid, Signal::SIGTERM);
+                        terminated_at = Some(Instant::now());
+                    }
+                    Some(at) if at.elapsed() >= TERM_GRACE_PERIOD => {
+                        trace!("Killing {:?} after grace period", pgid);
+                        let _ = killpg(pgid, Signal::SIGKILL);
+                        waitpid(pgid, None)?;
+                        return Ok(Waited::TimedOut {
+                            after: timeout,
+                            killed: true,
+                        });
+                    }
+                    _ => {}
+                }
+            }
+            thread::sleep(WAIT_POLL_INTERVAL);
+        }
+    }
+}
+
+impl Drop for Supervisor {
+    fn drop(&mut self) {
+        self.handle.close();
+    }
+}
+
+impl Interrupts {
+    /// Note the press, giving whether it quickly followed the previous one
+    fn note_press(&mut self) -> bool {
+        let now = Instant::now();
+        let quick = self
+            .last_press
+            .is_some_and(|last| now - last < INTERRUPT_GRACE_PERIOD);
+        self.last_press = Some(now);
+        quick
+    }
+
+    fn press(&mut self) {
+        let quick = self.note_press();
+        let Some(pgid) = self.pgid else {
+            warn!("Interrupted between scripts, aborting run");
+            self.abort = true;
+            return;
+        };
+        self.presses += 1;
+        if quick {
+            warn!("Interrupted again within grace period, aborting run");
+            self.abort = true;
+        }
+        let signal = match self.presses {
+            1 => Signal::SIGINT,
+            2 => Signal::SIGTERM,
+            _ => Signal::SIGKILL,
+        };
+        info!("Sending {} to process group {:?}", signal, pgid);
+        let _ = killpg(pgid, signal);
+    }
+
+    /// Count a press the terminal delivered to the script's group itself
+    fn pressed_directly(&mut self) {
+        let quick = self.note_press();
+        self.presses += 1;
+        if quick {
+            warn!("Interrupted again within grace period, aborting run");
+            self.abort = true;
+        }
+    }
+}
+
+/// Whether the script ended because of Ctrl-C, by the signal or the conventional exit code
+fn died_of_interrupt(status: WaitStatus) -> bool {
+    matches!(
+        status,
+        WaitStatus::Signaled(_, Signal::SIGINT, _) | WaitStatus::Exited(_, 130)
+    )
+}
+
+/// Make the process group the foreground of the terminal, if dottie holds it
+fn hand_terminal(pgid: Pid) -> bool {
+    let stdin = io::stdin();
+    if !stdin.is_terminal() || tcgetpgrp(&stdin).ok() != Some(getpgrp()) {
+        return false;
+    }
+    tcsetpgrp(&stdin, pgid).is_ok()
+}
+
+/// Take the terminal back after handing it to a script
+fn reclaim_terminal() {
+    // Changing the foreground from the background raises SIGTTOU, unless it is blocked
+    let mut ttou = SigSet::empty();
+    ttou.add(Signal::SIGTTOU);
+    let _ = ttou.thread_block();
+    if let Err(e) = tcsetpgrp(io::stdin(), getpgrp()) {
+        warn!("Failed to take back the terminal: {}", e);
+    }
+    let _ = ttou.thread_unblock();
+}
+
+fn exit_status(status: WaitStatus) -> ExitStatus {
+    match status {
+        WaitStatus::Exited(_, code) => ExitStatus::from_raw((code & 0xff) << 8),
+        WaitStatus::Signaled(_, signal, core_dumped) => {
+            ExitStatus::from_raw(signal as i32 | if core_dumped { 0x80 } else { 0 })
+        }
+        _ => ExitStatus::from_raw(0),
+    }
+}
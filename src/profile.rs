@@ -0,0 +1,184 @@
+use std::{collections::BTreeMap, fs, io, path::PathBuf};
+
+use log::trace;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    dir::{Dir, ensure_exists, expand_path, get, is_valid_pkg_name},
+    ledger::LinkMode,
+    manifest::Manifest,
+};
+
+pub static PROFILE_EXTENSION: &str = "toml";
+pub static MACHINE_FILE: &str = "machine.toml";
+
+#[derive(Debug, Error)]
+pub enum ProfileError {
+    #[error("`{0}` is not a valid profile name")]
+    InvalidName(String),
+    #[error("Profile `{0}` does not exist, expected it at {1}")]
+    NotFound(String, PathBuf),
+    #[error("Failed to read {0}: {1}")]
+    CannotRead(PathBuf, io::Error),
+    #[error("Malformed {0}: {1}")]
+    Malformed(PathBuf, toml::de::Error),
+    #[error("Invalid value for `{key}` in profile {path}: {reason}")]
+    InvalidValue {
+        path: PathBuf,
+        key: String,
+        reason: String,
+    },
+    #[error("Failed to serialize machine settings: {0}")]
+    CannotSerialize(toml::ser::Error),
+    #[error("Failed to write {0}: {1}")]
+    CannotWrite(PathBuf, io::Error),
+}
+
+/// A named set of packages, read from `~/.dottie/.profiles/<name>.toml`
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    /// Where the profile was loaded from
+    #[serde(skip)]
+    pub path: PathBuf,
+    /// What the profile is for
+    pub description: Option<String>,
+    /// Packages the profile selects, their dependencies are pulled in as usual
+    pub packages: Vec<String>,
+    /// Per package settings taking precedence over the package manifests
+    pub overrides: BTreeMap<String, PkgOverride>,
+}
+
+/// Manifest values a profile overrides for one package
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PkgOverride {
+    /// Replaces the manifest `target`
+    pub target: Option<String>,
+    /// Replaces the manifest `mode`
+    pub mode: Option<LinkMode>,
+    /// Extends the manifest `scripts.env`
+    pub env: BTreeMap<String, String>,
+}
+
+impl PkgOverride {
+    pub fn apply(&self, manifest: &mut Manifest) {
+        if let Some(target) = &self.target {
+            manifest.target = Some(target.to_owned());
+        }
+        if let Some(mode) = self.mode {
+            manifest.mode = mode;
+        }
+        manifest.scripts.env.extend(self.env.clone());
+    }
+}
+
+impl Profile {
+    pub fn path_of(name: &str) -> PathBuf {
+        get(Dir::Profiles).join(format!("{name}.{PROFILE_EXTENSION}"))
+    }
+
+    pub fn load(name: &str) -> eyre::Result<Self> {
+        if !is_valid_pkg_name(name) {
+            return Err(ProfileError::InvalidName(name.to_owned()).into());
+        }
+        let path = Self::path_of(name);
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(ProfileError::NotFound(name.to_owned(), path).into());
+            }
+            Err(e) => return Err(ProfileError::CannotRead(path, e).into()),
+        };
+        let mut profile: Self =
+            toml::from_str(&content).map_err(|e| ProfileError::Malformed(path.clone(), e))?;
+        profile.path = path;
+        profile.validate()?;
+        Ok(profile)
+    }
+
+    fn validate(&self) -> Result<(), ProfileError> {
+        for pkg_name in self.packages.iter().chain(self.overrides.keys()) {
+            if !is_valid_pkg_name(pkg_name) {
+                return Err(self.invalid("packages", format!("`{pkg_name}` is not a package name")));
+            }
+        }
+        for (pkg_name, over) in &self.overrides {
+            let key = format!("overrides.{pkg_name}");
+            if let Some(target) = &over.target {
+                expand_path(target).map_err(|e| self.invalid(&key, e.to_string()))?;
+            }
+            if over.mode == Some(LinkMode::Template) {
+                return Err(self.invalid(&key, "expected mode `symlink` or `copy`"));
+            }
+        }
+        Ok(())
+    }
+
+    fn invalid(&self, key: &str, reason: impl Into<String>) -> ProfileError {
+        ProfileError::InvalidValue {
+            path: self.path.clone(),
+            key: key.to_owned(),
+            reason: reason.into(),
+        }
+    }
+
+    /// Names of all profiles, sorted
+    pub fn all() -> eyre::Result<Vec<String>> {
+        let dir = get(Dir::Profiles);
+        let entries = match dir.read_dir() {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(ProfileError::CannotRead(dir, e).into()),
+        };
+        let mut names = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != PROFILE_EXTENSION) {
+                trace!("Skipping non profile entry: {:?}", path);
+                continue;
+            }
+            if let Some(name) = path.file_stem().and_then(|name| name.to_str()) {
+                names.push(name.to_owned());
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+}
+
+// LYN: Machine Settings
+
+/// Settings of this machine only, kept under the state directory rather than with the packages
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Machine {
+    /// Profile to use when no packages are given
+    pub profile: Option<String>,
+}
+
+impl Machine {
+    pub fn path() -> PathBuf {
+        get(Dir::State).join(MACHINE_FILE)
+    }
+
+    /// Load the machine settings, absent settings are default ones
+    pub fn load() -> eyre::Result<Self> {
+        let path = Self::path();
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(ProfileError::CannotRead(path, e).into()),
+        };
+        Ok(toml::from_str(&content).map_err(|e| ProfileError::Malformed(path, e))?)
+    }
+
+    pub fn save(&self) -> eyre::Result<()> {
+        ensure_exists(Dir::State)?;
+        let path = Self::path();
+        let content = toml::to_string(self).map_err(ProfileError::CannotSerialize)?;
+        fs::write(&path, content).map_err(|e| ProfileError::CannotWrite(path, e))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,369 @@
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use log::trace;
+use thiserror::Error;
+
+use crate::{
+    dir::{Dir, HOME_DIR, get},
+    host::Host,
+    variant::base_name,
+};
+
+pub static TEMPLATE_EXTENSION: &str = "tmpl";
+pub static VARS_FILE: &str = "vars.toml";
+
+#[derive(Debug, Error)]
+pub enum TemplateError {
+    #[error("Failed to read {0}: {1}")]
+    CannotRead(PathBuf, io::Error),
+    #[error("Malformed variables file {0}: {1}")]
+    MalformedVars(PathBuf, toml::de::Error),
+    #[error("Variable `{1}` in {0} must be a string, number or boolean")]
+    UnsupportedVar(PathBuf, String),
+    #[error("Unknown variable `{1}` at line {2} of {0}")]
+    UnknownVar(PathBuf, String, usize),
+    #[error("Malformed tag `{{{{{1}}}}}` at line {2} of {0}")]
+    MalformedTag(PathBuf, String, usize),
+    #[error("Unexpected `{{{{{1}}}}}` at line {2} of {0}")]
+    UnexpectedTag(PathBuf, String, usize),
+    #[error("Unclosed tag opened at line {1} of {0}")]
+    UnclosedTag(PathBuf, usize),
+}
+
+/// Variables available to templates, host facts overridden by `$XDG_STATE_HOME/dottie/vars.toml`
+#[derive(Debug, Clone, Default)]
+pub struct Vars(BTreeMap<String, String>);
+
+impl Vars {
+    pub fn load() -> eyre::Result<Self> {
+        let host = Host::current();
+        let mut vars = Self::default();
+        vars.set("hostname", host.hostname);
+        vars.set("os", host.os);
+        vars.set("distro", host.distro);
+        vars.set("arch", host.arch);
+        vars.set("user", host.user);
+        vars.set("home", HOME_DIR.to_string_lossy());
+
+        let path = get(Dir::State).join(VARS_FILE);
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vars),
+            Err(e) => return Err(TemplateError::CannotRead(path, e).into()),
+        };
+        let table: toml::Table =
+            toml::from_str(&content).map_err(|e| TemplateError::MalformedVars(path.clone(), e))?;
+        for (name, value) in table {
+            let value = match value {
+                toml::Value::String(value) => value,
+                toml::Value::Integer(value) => value.to_string(),
+                toml::Value::Float(value) => value.to_string(),
+                toml::Value::Boolean(value) => value.to_string(),
+                _ => return Err(TemplateError::UnsupportedVar(path, name).into()),
+            };
+            vars.set(&name, value);
+        }
+        Ok(vars)
+    }
+
+    pub fn set(&mut self, name: &str, value: impl Into<String>) {
+        self.0.insert(name.to_owned(), value.into());
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(String::as_str)
+    }
+}
+
+/// Whether the path names a template to be rendered, variant suffixes aside
+pub fn is_template(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| Path::new(base_name(name)).extension())
+        .is_some_and(|ext| ext == TEMPLATE_EXTENSION)
+}
+
+/// Whether anything inside the given directory is a template
+pub fn has_template_within(dir: &Path) -> io::Result<bool> {
+    for entry in dir.read_dir()? {
+        let path = entry?.path();
+        if path.is_dir() && !path.is_symlink() {
+            if has_template_within(&path)? {
+                return Ok(true);
+            }
+        } else if is_template(&path) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+// LYN: Rendering
+
+/// Render the template file with the given variables.
+///
+/// Supports `{{ var }}` substitution and `{{#if cond}}`, `{{else}}`, `{{/if}}` blocks, where
+/// `cond` is `var` (defined and non-empty), `var == "value"` or `var != "value"`.
+pub fn render(path: &Path, vars: &Vars) -> Result<String, TemplateError> {
+    trace!("Rendering template {:?}", path);
+    let source =
+        fs::read_to_string(path).map_err(|e| TemplateError::CannotRead(path.to_owned(), e))?;
+    render_str(path, &source, vars)
+}
+
+#[derive(Debug)]
+enum Token<'a> {
+    Text(&'a str),
+    Tag { body: &'a str, line: usize },
+}
+
+struct Frame {
+    /// Whether the enclosing block is being emitted
+    parent: bool,
+    /// Whether the condition held
+    cond: bool,
+    /// Whether `{{else}}` was seen
+    in_else: bool,
+    /// Where the block was opened
+    line: usize,
+}
+
+impl Frame {
+    fn emitting(&self) -> bool {
+        self.parent && (self.cond != self.in_else)
+    }
+}
+
+fn render_str(path: &Path, source: &str, vars: &Vars) -> Result<String, TemplateError> {
+    let mut out = String::with_capacity(source.len());
+    let mut frames: Vec<Frame> = Vec::new();
+    let emitting = |frames: &[Frame]| frames.last().is_none_or(Frame::emitting);
+
+    for token in tokenize(path, source)? {
+        match token {
+            Token::Text(text) => {
+                if emitting(&frames) {
+                    out.push_str(text);
+                }
+            }
+            Token::Tag { body, line } => {
+                let malformed = || TemplateError::MalformedTag(path.to_owned(), body.into(), line);
+                let unexpected =
+                    || TemplateError::UnexpectedTag(path.to_owned(), body.into(), line);
+                if let Some(cond) = body.strip_prefix("#if ") {
+                    frames.push(Frame {
+                        parent: emitting(&frames),
+                        cond: eval_cond(cond.trim(), vars).ok_or_else(malformed)?,
+                        in_else: false,
+                        line,
+                    });
+                } else if body == "else" {
+                    let frame = frames.last_mut().ok_or_else(unexpected)?;
+                    if frame.in_else {
+                        return Err(unexpected());
+                    }
+                    frame.in_else = true;
+                } else if body == "/if" {
+                    frames.pop().ok_or_else(unexpected)?;
+                } else if is_ident(body) {
+                    if emitting(&frames) {
+                        let value = vars.get(body).ok_or_else(|| {
+                            TemplateError::UnknownVar(path.to_owned(), body.into(), line)
+                        })?;
+                        out.push_str(value);
+                    }
+                } else {
+                    return Err(malformed());
+                }
+            }
+        }
+    }
+
+    if let Some(frame) = frames.last() {
+        return Err(TemplateError::UnclosedTag(path.to_owned(), frame.line));
+    }
+    Ok(out)
+}
+
+/// Split the source into text and tags, dropping the lines that hold nothing but a block tag
+fn tokenize<'a>(path: &Path, source: &'a str) -> Result<Vec<Token<'a>>, TemplateError> {
+    let mut tokens = Vec::new();
+    let mut rest = source;
+    let mut line = 1;
+    let mut at_line_start = true;
+
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start..].find("}}") else {
+            return Err(TemplateError::UnclosedTag(
+                path.to_owned(),
+                line + rest[..start].matches('\n').count(),
+            ));
+        };
+        let mut before = &rest[..start];
+        let body = rest[start + 2..start + len].trim();
+        let mut after = &rest[start + len + 2..];
+        let tag_line = line + before.matches('\n').count();
+
+        let mut standalone = false;
+        if body.starts_with('#') || body.starts_with('/') || body == "else" {
+            let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+            let standalone_start =
+                (line_start > 0 || at_line_start) && before[line_start..].trim().is_empty();
+            let line_end = after.find('\n').map_or(after.len(), |i| i + 1);
+            if standalone_start && after[..line_end].trim().is_empty() {
+                before = &before[..line_start];
+                after = &after[line_end..];
+                line += 1;
+                standalone = true;
+            }
+        }
+        at_line_start = standalone;
+
+        line += before.matches('\n').count();
+        if !before.is_empty() {
+            tokens.push(Token::Text(before));
+        }
+        tokens.push(Token::Tag {
+            body,
+            line: tag_line,
+        });
+        rest = after;
+    }
+    if !rest.is_empty() {
+        tokens.push(Token::Text(rest));
+    }
+
+    Ok(tokens)
+}
+
+fn eval_cond(cond: &str, vars: &Vars) -> Option<bool> {
+    for (op, negate) in [("==", false), ("!=", true)] {
+        if let Some((name, value)) = cond.split_once(op) {
+            let name = name.trim();
+            let value = value.trim().strip_prefix('"')?.strip_suffix('"')?;
+            if !is_ident(name) {
+                return None;
+            }
+            return Some((vars.get(name) == Some(value)) != negate);
+        }
+    }
+    if !is_ident(cond) {
+        return None;
+    }
+    Some(vars.get(cond).is_some_and(|value| !value.is_empty()))
+}
+
+fn is_ident(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars() -> Vars {
+        let mut vars = Vars::default();
+        vars.set("os", "linux");
+        vars.set("editor", "nvim");
+        vars.set("empty", "");
+        vars
+    }
+
+    fn render(source: &str) -> Result<String, TemplateError> {
+        render_str(Path::new("t.tmpl"), source, &vars())
+    }
+
+    #[test]
+    fn tokenize_drops_standalone_block_lines() {
+        let tokens = tokenize(Path::new("t"), "a\n{{#if os}}\nb {{ editor }}\n{{/if}}\nc").unwrap();
+        let shape: Vec<_> = tokens
+            .iter()
+            .map(|token| match token {
+                Token::Text(text) => format!("text {text:?}"),
+                Token::Tag { body, line } => format!("tag {body} @{line}"),
+            })
+            .collect();
+        assert_eq!(
+            shape,
+            [
+                "text \"a\\n\"",
+                "tag #if os @2",
+                "text \"b \"",
+                "tag editor @3",
+                "text \"\\n\"",
+                "tag /if @4",
+                "text \"c\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn render_substitutes_and_branches() {
+        assert_eq!(render("editor={{ editor }}\n").unwrap(), "editor=nvim\n");
+        assert_eq!(
+            render("{{#if os == \"macos\"}}\nmac\n{{else}}\nother\n{{/if}}\n").unwrap(),
+            "other\n"
+        );
+        assert_eq!(render("[{{#if empty}}x{{/if}}]").unwrap(), "[]");
+    }
+
+    #[test]
+    fn render_ignores_unknown_var_in_skipped_block() {
+        assert_eq!(
+            render("{{#if missing}}{{ missing }}{{/if}}ok").unwrap(),
+            "ok"
+        );
+    }
+
+    #[test]
+    fn render_rejects_unknown_var() {
+        assert!(matches!(
+            render("a\n{{ missing }}"),
+            Err(TemplateError::UnknownVar(_, name, 2)) if name == "missing"
+        ));
+    }
+
+    #[test]
+    fn render_rejects_unterminated_tag() {
+        assert!(matches!(
+            render("a\nb {{ editor"),
+            Err(TemplateError::UnclosedTag(_, 2))
+        ));
+        assert!(matches!(
+            render("{{#if os}}\nopen"),
+            Err(TemplateError::UnclosedTag(_, 1))
+        ));
+    }
+
+    #[test]
+    fn render_rejects_stray_block_tags() {
+        assert!(matches!(
+            render("{{/if}}"),
+            Err(TemplateError::UnexpectedTag(..))
+        ));
+        assert!(matches!(
+            render("{{#if os}}{{else}}{{else}}{{/if}}"),
+            Err(TemplateError::UnexpectedTag(..))
+        ));
+    }
+
+    #[test]
+    fn eval_cond_forms() {
+        let vars = vars();
+        assert_eq!(eval_cond("os", &vars), Some(true));
+        assert_eq!(eval_cond("empty", &vars), Some(false));
+        assert_eq!(eval_cond("missing", &vars), Some(false));
+        assert_eq!(eval_cond("os == \"linux\"", &vars), Some(true));
+        assert_eq!(eval_cond("os != \"linux\"", &vars), Some(false));
+        assert_eq!(eval_cond("missing != \"x\"", &vars), Some(true));
+        assert_eq!(eval_cond("os == linux", &vars), None);
+        assert_eq!(eval_cond("a b", &vars), None);
+    }
+}
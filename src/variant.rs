@@ -0,0 +1,242 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use log::{trace, warn};
+
+use crate::{
+    condition::{Condition, Specificity, first_unmet},
+    host::Host,
+};
+
+pub static VARIANT_SEPARATOR: &str = "##";
+
+/// The name with any variant suffix removed, `config##os.linux` becomes `config`
+pub fn base_name(name: &str) -> &str {
+    name.split_once(VARIANT_SEPARATOR)
+        .map_or(name, |(base, _)| base)
+}
+
+/// The variant suffix of the name, `config##os.linux` has `os.linux`
+pub fn suffix_of(name: &str) -> Option<&str> {
+    name.split_once(VARIANT_SEPARATOR).map(|(_, suffix)| suffix)
+}
+
+/// Whether the path names a variant, i.e. has a variant suffix
+pub fn is_variant(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name.to_string_lossy().contains(VARIANT_SEPARATOR))
+}
+
+/// Conditions of the variant suffix, `None` if the name has no suffix or it cannot be understood
+fn conditions_of(path: &Path) -> Option<Vec<Condition>> {
+    let suffix = suffix_of(path.file_name()?.to_str()?)?;
+    Condition::parse_list(suffix)
+        .inspect_err(|e| warn!("Never picking variant {:?}: {}", path, e))
+        .ok()
+}
+
+/// The entries of one directory after variants have been picked
+#[derive(Debug)]
+pub enum Pick {
+    /// An entry that has no variants
+    Plain(PathBuf),
+    /// The variant that best matches this machine
+    Chosen {
+        src_path: PathBuf,
+        reason: String,
+        others: usize,
+    },
+    /// No variant matches this machine, `src_path` is the name without suffix
+    NoMatch {
+        src_path: PathBuf,
+        candidates: usize,
+    },
+}
+
+/// Group entries by their name without variant suffix and pick the best match of each group.
+///
+/// A variant matches when all its comma separated conditions hold, the one with the most specific
+/// conditions wins (host, then user, distro, os, arch), equally specific ones go by name, and
+/// `##default` comes before a plain file of the same name.
+pub fn pick(entries: Vec<PathBuf>, host: &Host) -> Vec<Pick> {
+    let mut groups: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+    for entry in entries {
+        let base = match entry.file_name().and_then(|name| name.to_str()) {
+            Some(name) => entry.with_file_name(base_name(name)),
+            None => entry.clone(),
+        };
+        groups.entry(base).or_default().push(entry);
+    }
+
+    let mut picks = Vec::new();
+    for (base, mut group) in groups {
+        if group.len() == 1 && group[0] == base {
+            picks.push(Pick::Plain(group.remove(0)));
+            continue;
+        }
+        group.sort();
+
+        // A plain file scores `None`, below any variant
+        let mut best: Option<(Option<Specificity>, PathBuf, String)> = None;
+        for candidate in &group {
+            let (score, reason) = if *candidate == base {
+                (None, "plain file, no variant matches".to_owned())
+            } else {
+                let Some(conditions) = conditions_of(candidate) else {
+                    continue;
+                };
+                if first_unmet(&conditions, host).is_some() {
+                    continue;
+                }
+                let reason = if conditions.iter().all(|c| *c == Condition::Default) {
+                    "default, no other variant matches".to_owned()
+                } else {
+                    format!(
+                        "matches {}",
+                        conditions
+                            .iter()
+                            .filter(|condition| **condition != Condition::Default)
+                            .map(Condition::to_string)
+                            .collect::<Vec<_>>()
+                            .join(" and ")
+                    )
+                };
+                (Some(Specificity::of(&conditions)), reason)
+            };
+            if best
+                .as_ref()
+                .is_none_or(|(best_score, ..)| score > *best_score)
+            {
+                best = Some((score, candidate.to_owned(), reason));
+            }
+        }
+
+        match best {
+            Some((_, src_path, reason)) => {
+                trace!("Picked variant {:?}: {}", src_path, reason);
+                picks.push(Pick::Chosen {
+                    src_path,
+                    reason,
+                    others: group.len() - 1,
+                });
+            }
+            None => picks.push(Pick::NoMatch {
+                src_path: base,
+                candidates: group.len(),
+            }),
+        }
+    }
+    picks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host() -> Host {
+        Host {
+            hostname: "laptop".to_owned(),
+            os: "linux".to_owned(),
+            distro: "arch".to_owned(),
+            arch: "x86_64".to_owned(),
+            user: "me".to_owned(),
+        }
+    }
+
+    fn chosen(names: &[&str]) -> Option<String> {
+        let entries = names
+            .iter()
+            .map(|name| PathBuf::from("/p").join(name))
+            .collect();
+        match pick(entries, &host()).pop()? {
+            Pick::Chosen { src_path, .. } => Some(src_path.file_name()?.to_str()?.to_owned()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn names_and_suffixes() {
+        assert_eq!(base_name("config##os.linux"), "config");
+        assert_eq!(suffix_of("config##os.linux"), Some("os.linux"));
+        assert_eq!(suffix_of("config"), None);
+        assert!(is_variant(Path::new("/p/config##default")));
+    }
+
+    #[test]
+    fn pick_most_specific_match() {
+        assert_eq!(
+            chosen(&["config##os.linux", "config##host.laptop", "config##user.me"]).as_deref(),
+            Some("config##host.laptop")
+        );
+        assert_eq!(
+            chosen(&["config##os.linux", "config##distro.arch"]).as_deref(),
+            Some("config##distro.arch")
+        );
+        assert_eq!(
+            chosen(&["config##os.linux", "config##host.desktop"]).as_deref(),
+            Some("config##os.linux")
+        );
+    }
+
+    #[test]
+    fn pick_more_specific_key_over_several_less_specific() {
+        assert_eq!(
+            chosen(&[
+                "config##user.me,distro.arch,os.linux",
+                "config##host.laptop"
+            ])
+            .as_deref(),
+            Some("config##host.laptop")
+        );
+        assert_eq!(
+            chosen(&["config##arch.x86_64,distro.arch", "config##user.me"]).as_deref(),
+            Some("config##user.me")
+        );
+        assert_eq!(
+            chosen(&["config##os.linux", "config##os.linux,arch.x86_64"]).as_deref(),
+            Some("config##os.linux,arch.x86_64")
+        );
+    }
+
+    #[test]
+    fn pick_tie_goes_to_first_name() {
+        assert_eq!(
+            chosen(&[
+                "config##os.linux,arch.x86_64",
+                "config##arch.x86_64,os.linux"
+            ])
+            .as_deref(),
+            Some("config##arch.x86_64,os.linux")
+        );
+    }
+
+    #[test]
+    fn pick_default_before_plain_file() {
+        assert_eq!(
+            chosen(&["config", "config##default", "config##os.macos"]).as_deref(),
+            Some("config##default")
+        );
+        assert_eq!(
+            chosen(&["config", "config##os.macos"]).as_deref(),
+            Some("config")
+        );
+    }
+
+    #[test]
+    fn pick_plain_and_no_match() {
+        let entries = vec![
+            PathBuf::from("/p/plain"),
+            PathBuf::from("/p/only##os.macos"),
+        ];
+        let picks = pick(entries, &host());
+        assert!(matches!(
+            &picks[..],
+            [
+                Pick::NoMatch { src_path, candidates: 1 },
+                Pick::Plain(plain),
+            ] if *src_path == Path::new("/p/only") && *plain == Path::new("/p/plain")
+        ));
+    }
+}